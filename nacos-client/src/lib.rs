@@ -1,8 +1,21 @@
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        let result = 2 + 2;
-        assert_eq!(result, 4);
-    }
-}
+//! the crate most applications depend on: one builder for the nacos clients sharing a server list
+//! and credentials, with the client crates re-exported behind features.
+//!
+//! only service discovery is supported, over the v1 open api with the `http` feature; config management
+//! and the nacos 2.x grpc transport are not implemented, so there are no features for them yet
+
+#[cfg(feature = "http")]
+mod client;
+
+#[cfg(feature = "http")]
+pub use client::{NacosClient, NacosClientBuilder};
+#[cfg(feature = "http")]
+pub use nacos_naming_client::HttpNamingRemote;
+#[cfg(feature = "naming")]
+pub use nacos_naming_client::{
+    self as naming, error, model, constants,
+    NamingClient, NamingConfig, NamingConfigBuilder, PushConfig, ServerConfig, NamingRemote,
+    ServiceChangeListener, Selector, Matcher, InstanceSyncer, ShutdownOptions
+};
+#[cfg(feature = "tonic")]
+pub use nacos_tonic_adapter as tonic;
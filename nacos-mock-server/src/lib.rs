@@ -0,0 +1,251 @@
+//! in-process nacos server for tests: the v1 naming open api kept in memory,
+//! with udp pushes to the clients which queried a changed service.
+//! config endpoints and the 2.x grpc api are not covered yet
+mod naming;
+mod server;
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}},
+    time::Duration
+};
+
+use hyper::service::{make_service_fn, service_fn};
+use tokio::{net::UdpSocket, sync::oneshot, task::JoinHandle};
+
+pub use naming::{MockInstance, DEFAULT_GROUP, DEFAULT_CLUSTER};
+use server::{Counters, Settings, State};
+
+
+/// requests handled by the mock server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MockStats {
+    pub requests: u64,
+    pub logins: u64,
+    pub beats: u64,
+    pub pushes: u64,
+    /// ack packets received from the push receivers of the clients
+    pub push_acks: u64
+}
+
+pub struct MockServerBuilder {
+    settings: Settings
+}
+
+impl Default for MockServerBuilder {
+    fn default() -> Self {
+        MockServerBuilder {
+            settings: Settings {
+                context_path: "/nacos".to_owned(),
+                credentials: None,
+                token_ttl: 18000,
+                beat_interval: Duration::from_secs(5),
+                instance_timeout: None
+            }
+        }
+    }
+}
+
+impl MockServerBuilder {
+    /// `nacos` by default, `""` serves the api at the root
+    pub fn context_path(mut self, context_path: &str) -> Self {
+        let context_path = context_path.trim_matches('/');
+        self.settings.context_path = if context_path.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", context_path)
+        };
+        self
+    }
+
+    /// require a login with these credentials and an `accessToken` on every naming request
+    pub fn auth(mut self, username: &str, password: &str) -> Self {
+        self.settings.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    /// seconds, returned by login; requests with an expired token are rejected with 403
+    pub fn token_ttl(mut self, token_ttl: u64) -> Self {
+        self.settings.token_ttl = token_ttl;
+        self
+    }
+
+    /// `clientBeatInterval` of the beat acks; the rust client beats 2s earlier, so keep it above 2s
+    pub fn beat_interval(mut self, beat_interval: Duration) -> Self {
+        self.settings.beat_interval = beat_interval;
+        self
+    }
+
+    /// remove ephemeral instances without a beat for `timeout`; instances never expire by default
+    pub fn instance_timeout(mut self, timeout: Duration) -> Self {
+        self.settings.instance_timeout = Some(timeout);
+        self
+    }
+
+    /// listen on a random local port
+    pub async fn start(self) -> std::io::Result<MockServer> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let push_socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let context_path = self.settings.context_path.clone();
+        let instance_timeout = self.settings.instance_timeout;
+        let state = Arc::new(State {
+            settings: self.settings,
+            services: Mutex::new(HashMap::new()),
+            tokens: Mutex::new(HashMap::new()),
+            counters: Counters::default(),
+            available: AtomicBool::new(true),
+            push_socket
+        });
+
+        let (tx, rx) = oneshot::channel::<()>();
+        let service_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = service_state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| server::handle(state.clone(), request)))
+            }
+        });
+        let http = hyper::Server::from_tcp(listener)
+            .map_err(std::io::Error::other)?
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                let _ = rx.await;
+            });
+        let mut tasks = vec![tokio::spawn(async move {
+            if let Err(error) = http.await {
+                log::error!("[mock] http server stopped: {}", error);
+            }
+        })];
+
+        let ack_state = state.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut buf = [0; 65536];
+            while ack_state.push_socket.recv_from(&mut buf).await.is_ok() {
+                ack_state.counters.push_acks.fetch_add(1, Ordering::Relaxed);
+            }
+        }));
+        if let Some(timeout) = instance_timeout {
+            let expire_state = state.clone();
+            tasks.push(tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    expire_state.expire(timeout).await;
+                }
+            }));
+        }
+        log::info!("[mock] nacos mock server listening on {}{}", addr, context_path);
+        Ok(MockServer { addr, state, shutdown: Some(tx), tasks })
+    }
+}
+
+/// a nacos server for one test, stopped when dropped
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<State>,
+    shutdown: Option<oneshot::Sender<()>>,
+    tasks: Vec<JoinHandle<()>>
+}
+
+impl MockServer {
+    pub fn builder() -> MockServerBuilder {
+        MockServerBuilder::default()
+    }
+
+    /// a server with the default settings and no auth
+    pub async fn start() -> std::io::Result<Self> {
+        MockServerBuilder::default().start().await
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// `host:port`, to be combined with [MockServer::context_path]
+    pub fn server_addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// without leading and trailing `/`
+    pub fn context_path(&self) -> &str {
+        self.state.settings.context_path.trim_start_matches('/')
+    }
+
+    /// `http://host:port/context_path`, like the entries of `serverAddr`
+    pub fn url(&self) -> String {
+        format!("http://{}{}", self.addr, self.state.settings.context_path)
+    }
+
+    pub fn stats(&self) -> MockStats {
+        let counters = &self.state.counters;
+        MockStats {
+            requests: counters.requests.load(Ordering::Relaxed),
+            logins: counters.logins.load(Ordering::Relaxed),
+            beats: counters.beats.load(Ordering::Relaxed),
+            pushes: counters.pushes.load(Ordering::Relaxed),
+            push_acks: counters.push_acks.load(Ordering::Relaxed)
+        }
+    }
+
+    /// if false every request is answered with 503, like a server that is starting or overloaded
+    pub fn set_available(&self, available: bool) {
+        self.state.available.store(available, Ordering::Relaxed);
+    }
+
+    /// instances of a service, sorted by `ip:port`
+    pub fn instances(&self, namespace_id: &str, group_name: &str, service_name: &str) -> Vec<MockInstance> {
+        let key = (namespace_id.to_string(), naming::grouped_name(service_name, Some(group_name)));
+        self.state.services.lock().expect("[mock] lock poisoned")
+            .get(&key)
+            .map(|service| service.instances.values().map(|(instance, _)| instance.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// number of push receivers which queried the service
+    pub fn subscribers(&self, namespace_id: &str, group_name: &str, service_name: &str) -> usize {
+        let key = (namespace_id.to_string(), naming::grouped_name(service_name, Some(group_name)));
+        self.state.services.lock().expect("[mock] lock poisoned")
+            .get(&key)
+            .map(|service| service.subscribers.len())
+            .unwrap_or_default()
+    }
+
+    /// register an instance on the server side, e.g. another client; subscribers get a push
+    pub async fn register_instance(&self, namespace_id: &str, group_name: &str, service_name: &str, instance: MockInstance) {
+        let key = (namespace_id.to_string(), naming::grouped_name(service_name, Some(group_name)));
+        self.state.upsert(key, instance).await
+    }
+
+    /// remove an instance on the server side; subscribers get a push
+    pub async fn deregister_instance(&self, namespace_id: &str, group_name: &str, service_name: &str, ip: &str, port: u16) {
+        let service_name = naming::grouped_name(service_name, Some(group_name));
+        self.state.remove(namespace_id, service_name.as_str(), format!("{}:{}", ip, port).as_str()).await
+    }
+
+    /// stop listening and wait for the server tasks
+    pub async fn shutdown(mut self) {
+        self.stop();
+        for task in std::mem::take(&mut self.tasks) {
+            let _ = task.await;
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        // the http server ends by the signal, the other tasks loop forever
+        for task in self.tasks.iter().skip(1) {
+            task.abort();
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    time::{Instant, SystemTime, UNIX_EPOCH}
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+pub const DEFAULT_GROUP: &str = "DEFAULT_GROUP";
+pub const DEFAULT_CLUSTER: &str = "DEFAULT";
+const SERVICE_INFO_SPLITER: &str = "@@";
+
+/// an instance kept by the mock server
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockInstance {
+    pub ip: String,
+    pub port: u16,
+    pub weight: f64,
+    pub healthy: bool,
+    pub enabled: bool,
+    pub ephemeral: bool,
+    pub cluster_name: String,
+    pub metadata: HashMap<String, String>
+}
+
+impl MockInstance {
+    /// a healthy, enabled, ephemeral instance of the default cluster
+    pub fn new(ip: &str, port: u16) -> Self {
+        MockInstance {
+            ip: ip.to_string(),
+            port,
+            weight: 1f64,
+            healthy: true,
+            enabled: true,
+            ephemeral: true,
+            cluster_name: DEFAULT_CLUSTER.to_owned(),
+            metadata: HashMap::new()
+        }
+    }
+
+    pub fn key(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+
+    fn to_json(&self, service_name: &str) -> Value {
+        json!({
+            "instanceId": format!("{}#{}#{}#{}", self.ip, self.port, self.cluster_name, service_name),
+            "ip": self.ip,
+            "port": self.port,
+            "weight": self.weight,
+            "healthy": self.healthy,
+            "enabled": self.enabled,
+            "ephemeral": self.ephemeral,
+            "clusterName": self.cluster_name,
+            "serviceName": service_name,
+            "metadata": self.metadata
+        })
+    }
+}
+
+/// the `beat` parameter of a beat request
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ClientBeat {
+    pub ip: String,
+    pub port: u16,
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+    pub cluster: Option<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>
+}
+
+fn default_weight() -> f64 {
+    1f64
+}
+
+impl From<ClientBeat> for MockInstance {
+    fn from(beat: ClientBeat) -> Self {
+        let mut instance = MockInstance::new(beat.ip.as_str(), beat.port);
+        instance.weight = beat.weight;
+        instance.metadata = beat.metadata;
+        if let Some(cluster) = beat.cluster {
+            instance.cluster_name = cluster;
+        }
+        instance
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Service {
+    /// key: ip:port, with the time of the last beat
+    pub instances: BTreeMap<String, (MockInstance, Instant)>,
+    /// json of the cluster settings, key: cluster name
+    pub clusters: HashMap<String, Value>,
+    pub last_ref_time: u64,
+    /// udp address of a subscriber and the clusters it queried
+    pub subscribers: HashMap<SocketAddr, String>
+}
+
+impl Service {
+    /// a change of the instances, pushes carry a newer last_ref_time
+    pub fn touch(&mut self) {
+        self.last_ref_time = now_millis().max(self.last_ref_time + 1);
+    }
+
+    pub fn service_info(&self, service_name: &str, clusters: &str, healthy_only: bool) -> Value {
+        let wanted = clusters.split(',').map(str::trim).filter(|cluster| !cluster.is_empty()).collect::<Vec<_>>();
+        let hosts = self.instances.values()
+            .map(|(instance, _)| instance)
+            .filter(|instance| wanted.is_empty() || wanted.contains(&instance.cluster_name.as_str()))
+            .filter(|instance| !healthy_only || instance.healthy)
+            .map(|instance| instance.to_json(service_name))
+            .collect::<Vec<_>>();
+        json!({
+            "name": service_name,
+            "groupName": service_name.split_once(SERVICE_INFO_SPLITER).map(|(group, _)| group).unwrap_or(DEFAULT_GROUP),
+            "clusters": clusters,
+            "cacheMillis": 10000,
+            "hosts": hosts,
+            "lastRefTime": self.last_ref_time,
+            "checksum": "",
+            "allIPs": false,
+            "reachProtectionThreshold": false
+        })
+    }
+
+    /// udp packets for every subscriber of the service
+    pub fn push_packets(&self, service_name: &str) -> Vec<(SocketAddr, Vec<u8>)> {
+        self.subscribers.iter()
+            .map(|(addr, clusters)| {
+                let packet = json!({
+                    "type": "dom",
+                    "lastRefTime": self.last_ref_time,
+                    "data": self.service_info(service_name, clusters, false).to_string()
+                });
+                (*addr, packet.to_string().into_bytes())
+            })
+            .collect()
+    }
+}
+
+/// `group@@service`; a name which already has a group is kept
+pub fn grouped_name(service_name: &str, group_name: Option<&str>) -> String {
+    if service_name.contains(SERVICE_INFO_SPLITER) {
+        return service_name.to_string();
+    }
+    format!("{}{}{}", group_name.unwrap_or(DEFAULT_GROUP), SERVICE_INFO_SPLITER, service_name)
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_millis() as u64).unwrap_or_default()
+}
@@ -0,0 +1,364 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU64, Ordering}},
+    time::{Duration, Instant}
+};
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use tokio::net::UdpSocket;
+
+use crate::naming::{ClientBeat, MockInstance, Service, grouped_name};
+
+const OK_CODE: u32 = 10200;
+const RESOURCE_NOT_FOUND_CODE: u32 = 20404;
+const DEFAULT_NAMESPACE: &str = "public";
+
+pub(crate) struct Settings {
+    pub context_path: String,
+    pub credentials: Option<(String, String)>,
+    pub token_ttl: u64,
+    pub beat_interval: Duration,
+    pub instance_timeout: Option<Duration>
+}
+
+#[derive(Default)]
+pub(crate) struct Counters {
+    pub requests: AtomicU64,
+    pub logins: AtomicU64,
+    pub beats: AtomicU64,
+    pub pushes: AtomicU64,
+    pub push_acks: AtomicU64
+}
+
+pub(crate) struct State {
+    pub settings: Settings,
+    /// key: (namespace, group@@service)
+    pub services: Mutex<HashMap<(String, String), Service>>,
+    /// access tokens and when they expire
+    pub tokens: Mutex<HashMap<String, Instant>>,
+    pub counters: Counters,
+    pub available: AtomicBool,
+    pub push_socket: UdpSocket
+}
+
+type Reply = Result<Response<Body>, (StatusCode, String)>;
+
+struct Params(HashMap<String, String>);
+
+impl Params {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str).filter(|value| !value.is_empty())
+    }
+
+    fn required(&self, key: &str) -> Result<&str, (StatusCode, String)> {
+        self.get(key).ok_or_else(|| (StatusCode::BAD_REQUEST, format!("Param '{}' is required.", key)))
+    }
+
+    fn parse<T: FromStr>(&self, key: &str, default: T) -> Result<T, (StatusCode, String)> {
+        match self.get(key) {
+            Some(value) => value.parse()
+                .map_err(|_| (StatusCode::BAD_REQUEST, format!("invalid value of {}: {}", key, value))),
+            None => Ok(default)
+        }
+    }
+
+    fn namespace(&self) -> String {
+        self.get("namespaceId").unwrap_or(DEFAULT_NAMESPACE).to_string()
+    }
+
+    fn service_key(&self) -> Result<(String, String), (StatusCode, String)> {
+        let service_name = self.required("serviceName")?;
+        Ok((self.namespace(), grouped_name(service_name, self.get("groupName"))))
+    }
+
+    fn instance(&self) -> Result<MockInstance, (StatusCode, String)> {
+        let mut instance = MockInstance::new(self.required("ip")?, self.parse("port", 0u16)?);
+        instance.weight = self.parse("weight", 1f64)?;
+        instance.healthy = self.parse("healthy", true)?;
+        instance.enabled = self.parse("enabled", self.parse("enable", true)?)?;
+        instance.ephemeral = self.parse("ephemeral", true)?;
+        if let Some(cluster) = self.get("clusterName") {
+            instance.cluster_name = cluster.to_string();
+        }
+        if let Some(metadata) = self.get("metadata") {
+            instance.metadata = serde_json::from_str(metadata)
+                .map_err(|error| (StatusCode::BAD_REQUEST, format!("invalid metadata: {}", error)))?;
+        }
+        Ok(instance)
+    }
+}
+
+fn text(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder().status(status).body(Body::from(body)).expect("[mock] invalid response")
+}
+
+fn ok() -> Reply {
+    Ok(text(StatusCode::OK, "ok".to_owned()))
+}
+
+fn json(value: Value) -> Reply {
+    Ok(Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(value.to_string()))
+        .expect("[mock] invalid response"))
+}
+
+pub(crate) async fn handle(state: Arc<State>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    state.counters.requests.fetch_add(1, Ordering::Relaxed);
+    if !state.available.load(Ordering::Relaxed) {
+        return Ok(text(StatusCode::SERVICE_UNAVAILABLE, "server is starting".to_owned()));
+    }
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let query = request.uri().query().unwrap_or_default().to_string();
+    let body = hyper::body::to_bytes(request.into_body()).await.unwrap_or_default();
+    let mut params = serde_urlencoded::from_str::<HashMap<String, String>>(query.as_str()).unwrap_or_default();
+    params.extend(serde_urlencoded::from_bytes::<HashMap<String, String>>(&body).unwrap_or_default());
+    let params = Params(params);
+
+    let path = match path.strip_prefix(state.settings.context_path.as_str()) {
+        Some(path) => path,
+        None => return Ok(text(StatusCode::NOT_FOUND, format!("no such api: {}", path)))
+    };
+    log::debug!("[mock] {} {}", method, path);
+    let reply = route(&state, &method, path, &params).await;
+    Ok(reply.unwrap_or_else(|(status, message)| text(status, format!("caused: {};", message))))
+}
+
+async fn route(state: &State, method: &Method, path: &str, params: &Params) -> Reply {
+    if path == "/v1/auth/users/login" && method == Method::POST {
+        return login(state, params);
+    }
+    if state.settings.credentials.is_some() {
+        let valid = params.get("accessToken")
+            .and_then(|token| state.tokens.lock().expect("[mock] lock poisoned").get(token).copied())
+            .map(|expires_at| expires_at > Instant::now())
+            .unwrap_or(false);
+        if !valid {
+            return Err((StatusCode::FORBIDDEN, "token invalid!".to_owned()));
+        }
+    }
+    match (method.clone(), path) {
+        (Method::POST, "/v1/ns/instance") => register(state, params).await,
+        (Method::DELETE, "/v1/ns/instance") => deregister(state, params).await,
+        (Method::PUT, "/v1/ns/instance") => update(state, params).await,
+        (Method::GET, "/v1/ns/instance/list") => list(state, params),
+        (Method::PUT, "/v1/ns/instance/beat") => beat(state, params).await,
+        (Method::GET, "/v1/ns/service") => service(state, params),
+        (Method::GET, "/v1/ns/service/list") => service_list(state, params),
+        (Method::PUT, "/v1/ns/cluster") => cluster(state, params),
+        _ => Err((StatusCode::NOT_FOUND, format!("no such api: {} {}", method, path)))
+    }
+}
+
+fn login(state: &State, params: &Params) -> Reply {
+    let username = params.required("username")?;
+    let password = params.required("password")?;
+    if let Some((expected_user, expected_password)) = state.settings.credentials.as_ref() {
+        if username != expected_user || password != expected_password {
+            return Err((StatusCode::FORBIDDEN, "unknown user!".to_owned()));
+        }
+    }
+    state.counters.logins.fetch_add(1, Ordering::Relaxed);
+    let token = uuid::Uuid::new_v4().to_string();
+    let now = Instant::now();
+    let mut tokens = state.tokens.lock().expect("[mock] lock poisoned");
+    tokens.retain(|_, expires_at| *expires_at > now);
+    tokens.insert(token.clone(), now + Duration::from_secs(state.settings.token_ttl));
+    drop(tokens);
+    json(json!({"accessToken": token, "tokenTtl": state.settings.token_ttl, "globalAdmin": true}))
+}
+
+async fn register(state: &State, params: &Params) -> Reply {
+    let key = params.service_key()?;
+    let instance = params.instance()?;
+    state.upsert(key, instance).await;
+    ok()
+}
+
+async fn deregister(state: &State, params: &Params) -> Reply {
+    let (namespace, service_name) = params.service_key()?;
+    let instance_key = format!("{}:{}", params.required("ip")?, params.parse("port", 0u16)?);
+    state.remove(&namespace, &service_name, &instance_key).await;
+    ok()
+}
+
+async fn update(state: &State, params: &Params) -> Reply {
+    let key = params.service_key()?;
+    let instance = params.instance()?;
+    let exists = state.services.lock().expect("[mock] lock poisoned")
+        .get(&key)
+        .map(|service| service.instances.contains_key(instance.key().as_str()))
+        .unwrap_or(false);
+    if !exists {
+        return Err((StatusCode::BAD_REQUEST, format!("instance not exist: {}", instance.key())));
+    }
+    state.upsert(key, instance).await;
+    ok()
+}
+
+fn list(state: &State, params: &Params) -> Reply {
+    let key = params.service_key()?;
+    let clusters = params.get("clusters").unwrap_or_default();
+    let healthy_only = params.parse("healthyOnly", false)?;
+    let subscriber = match (params.get("clientIP"), params.parse("udpPort", 0u16)?) {
+        (Some(ip), port) if port > 0 => format!("{}:{}", ip, port).parse::<SocketAddr>().ok(),
+        _ => None
+    };
+    let mut services = state.services.lock().expect("[mock] lock poisoned");
+    let service = services.entry(key.clone()).or_default();
+    if let Some(subscriber) = subscriber {
+        service.subscribers.insert(subscriber, clusters.to_string());
+    }
+    json(service.service_info(key.1.as_str(), clusters, healthy_only))
+}
+
+async fn beat(state: &State, params: &Params) -> Reply {
+    let key = params.service_key()?;
+    let client_beat = params.get("beat")
+        .map(serde_json::from_str::<ClientBeat>)
+        .transpose()
+        .map_err(|error| (StatusCode::BAD_REQUEST, format!("invalid beat: {}", error)))?;
+    state.counters.beats.fetch_add(1, Ordering::Relaxed);
+    let instance_key = match client_beat.as_ref() {
+        Some(client_beat) => format!("{}:{}", client_beat.ip, client_beat.port),
+        None => format!("{}:{}", params.required("ip")?, params.parse("port", 0u16)?)
+    };
+    let found = {
+        let mut services = state.services.lock().expect("[mock] lock poisoned");
+        match services.get_mut(&key).and_then(|service| service.instances.get_mut(instance_key.as_str())) {
+            Some((_, last_beat)) => {
+                *last_beat = Instant::now();
+                true
+            },
+            None => false
+        }
+    };
+    let interval = state.settings.beat_interval.as_millis() as u64;
+    if found {
+        return json(json!({"clientBeatInterval": interval, "code": OK_CODE, "lightBeatEnabled": false}));
+    }
+    match client_beat {
+        // like nacos, an expired instance is registered again from its beat
+        Some(client_beat) => {
+            state.upsert(key, client_beat.into()).await;
+            json(json!({"clientBeatInterval": interval, "code": OK_CODE, "lightBeatEnabled": false}))
+        },
+        None => json(json!({"clientBeatInterval": interval, "code": RESOURCE_NOT_FOUND_CODE, "lightBeatEnabled": true}))
+    }
+}
+
+fn service(state: &State, params: &Params) -> Reply {
+    let key = params.service_key()?;
+    let services = state.services.lock().expect("[mock] lock poisoned");
+    if !services.contains_key(&key) {
+        return Err((StatusCode::BAD_REQUEST, format!("specified service not exist, serviceName : {}", key.1)));
+    }
+    let (group_name, name) = key.1.split_once("@@").expect("[mock] never happen");
+    json(json!({
+        "namespaceId": key.0,
+        "name": name,
+        "groupName": group_name,
+        "appName": "",
+        "protectionThreshold": 0.0,
+        "metadata": {},
+        "selector": {"type": "none"}
+    }))
+}
+
+fn service_list(state: &State, params: &Params) -> Reply {
+    let namespace = params.namespace();
+    let prefix = format!("{}@@", params.get("groupName").unwrap_or(crate::naming::DEFAULT_GROUP));
+    let page_no = params.parse("pageNo", 1usize)?.max(1);
+    let page_size = params.parse("pageSize", 10usize)?;
+    let services = state.services.lock().expect("[mock] lock poisoned");
+    let mut names = services.keys()
+        .filter(|(ns, _)| *ns == namespace)
+        .filter_map(|(_, name)| name.strip_prefix(prefix.as_str()))
+        .collect::<Vec<_>>();
+    names.sort();
+    let doms = names.iter().skip((page_no - 1) * page_size).take(page_size).collect::<Vec<_>>();
+    json(json!({"count": names.len(), "doms": doms}))
+}
+
+fn cluster(state: &State, params: &Params) -> Reply {
+    let key = params.service_key()?;
+    let cluster_name = params.required("clusterName")?;
+    let health_checker = params.get("healthChecker")
+        .map(serde_json::from_str::<Value>)
+        .transpose()
+        .map_err(|error| (StatusCode::BAD_REQUEST, format!("invalid health checker: {}", error)))?;
+    let cluster = json!({
+        "checkPort": params.parse("checkPort", 0u16)?,
+        "useInstancePort4Check": params.parse("useInstancePort4Check", true)?,
+        "healthChecker": health_checker
+    });
+    state.services.lock().expect("[mock] lock poisoned")
+        .entry(key)
+        .or_default()
+        .clusters
+        .insert(cluster_name.to_string(), cluster);
+    ok()
+}
+
+impl State {
+    pub async fn upsert(&self, key: (String, String), instance: MockInstance) {
+        let packets = {
+            let mut services = self.services.lock().expect("[mock] lock poisoned");
+            let service = services.entry(key.clone()).or_default();
+            service.instances.insert(instance.key(), (instance, Instant::now()));
+            service.touch();
+            service.push_packets(key.1.as_str())
+        };
+        self.push(packets).await
+    }
+
+    pub async fn remove(&self, namespace: &str, service_name: &str, instance_key: &str) {
+        let packets = {
+            let mut services = self.services.lock().expect("[mock] lock poisoned");
+            let service = match services.get_mut(&(namespace.to_string(), service_name.to_string())) {
+                Some(service) => service,
+                None => return
+            };
+            if service.instances.remove(instance_key).is_none() {
+                return;
+            }
+            service.touch();
+            service.push_packets(service_name)
+        };
+        self.push(packets).await
+    }
+
+    /// drop the ephemeral instances without a beat within the timeout
+    pub async fn expire(&self, timeout: Duration) {
+        let packets = {
+            let mut services = self.services.lock().expect("[mock] lock poisoned");
+            let mut packets = vec![];
+            for ((_, service_name), service) in services.iter_mut() {
+                let before = service.instances.len();
+                service.instances.retain(|_, (instance, last_beat)| !instance.ephemeral || last_beat.elapsed() < timeout);
+                if service.instances.len() != before {
+                    service.touch();
+                    packets.extend(service.push_packets(service_name));
+                }
+            }
+            packets
+        };
+        self.push(packets).await
+    }
+
+    async fn push(&self, packets: Vec<(SocketAddr, Vec<u8>)>) {
+        for (addr, packet) in packets {
+            match self.push_socket.send_to(&packet, addr).await {
+                Ok(_) => {
+                    self.counters.pushes.fetch_add(1, Ordering::Relaxed);
+                },
+                Err(error) => log::warn!("[mock] failed to push to {}: {}", addr, error)
+            }
+        }
+    }
+}
@@ -1,24 +1,88 @@
+use std::{collections::HashMap, path::Path, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::Duration};
+
+use futures::future::join_all;
 use itertools::Itertools;
+use tokio::{sync::Mutex, time::Instant};
 
 use crate::{
     config::NamingConfig, 
     net::NamingRemote, 
-    error::Result, 
+    error::{Error, Result, batch_result}, 
     data::{
-        ServiceHolder, HeartBeatReactor, 
-        model::*, ServiceChangeListener, AccessTokenHolder, 
-    }, util, HttpNamingRemote
+        ServiceHolder, HeartBeatReactor, FailoverReactor,
+        model::*, ServiceChangeListener, ServiceUpdater, AccessTokenHolder, migrate_legacy_cache
+    }, monitor::Monitor, util, HttpNamingRemote, PushStats, Selector
 };
 
+/// what [NamingClient::shutdown] cleans up
+#[derive(Debug, Clone)]
+pub struct ShutdownOptions {
+    /// deregister the ephemeral instances registered by this client instead of letting their beats expire
+    pub deregister_ephemeral: bool,
+    /// deregister the persistent instances registered by this client; they outlive the client otherwise
+    pub deregister_persistent: bool,
+    /// time for deregistration and for the background tasks to stop
+    pub timeout: Duration
+}
+
+impl Default for ShutdownOptions {
+    fn default() -> Self {
+        ShutdownOptions {
+            deregister_ephemeral: true,
+            deregister_persistent: false,
+            timeout: Duration::from_secs(5)
+        }
+    }
+}
+
 pub struct NamingClient<R: NamingRemote> {
     config: NamingConfig,
     remote: R,
     service_holder: ServiceHolder,
     token_holder: AccessTokenHolder<R>,
-    beat_reactor: HeartBeatReactor<R>
+    beat_reactor: HeartBeatReactor<R>,
+    failover_reactor: FailoverReactor,
+    /// instances registered by this client, key: {service_name}#{ip}:{port}
+    owned: Mutex<HashMap<String, Instance>>,
+    closed: AtomicBool
+}
+
+fn owned_key(instance: &Instance) -> String {
+    format!("{}#{}", instance.service_name, instance.key())
 }
 
 impl<R: NamingRemote> NamingClient<R> {
+    fn check_open(&self) -> Result<()> {
+        if self.closed.load(Ordering::Acquire) {
+            Err(Error::Closed)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// signal every background task to stop without waiting for it
+    fn close_tasks(&self) {
+        self.remote.close();
+        self.beat_reactor.tasks().close();
+        self.failover_reactor.tasks().close();
+        self.token_holder.tasks().close();
+    }
+
+    fn monitor(config: &NamingConfig) -> Result<Monitor> {
+        #[cfg(feature = "prometheus")]
+        if let Some(registry) = config.metrics_registry.as_ref() {
+            return Ok(Monitor::new(registry, config.namespace_id.as_str())?);
+        }
+        let _ = config;
+        Ok(Monitor::default())
+    }
+
+    async fn service_holder(config: &NamingConfig, monitor: Monitor) -> Result<ServiceHolder> {
+        // namespaces must not share cache files
+        let cache_dir = Path::new(config.cache_dir.as_str()).join(config.namespace_id.as_str());
+        migrate_legacy_cache(Path::new(config.cache_dir.as_str()), config.namespace_id.as_str()).await;
+        ServiceHolder::new(cache_dir, config.update_when_empty, config.load_at_start, monitor).await
+    }
 
     pub fn get_group(&self) -> &str {
         self.config.group.as_str()
@@ -27,53 +91,239 @@ impl<R: NamingRemote> NamingClient<R> {
     pub fn get_cluster(&self) -> &str {
         self.config.cluster.as_str()
     }
+
+    /// labels of this client from the config
+    pub fn labels(&self) -> &HashMap<String, String> {
+        &self.config.labels
+    }
 }
 
 impl NamingClient<HttpNamingRemote> {
-    pub async fn new_http(config: NamingConfig) -> Self {
-        let service_holder = match ServiceHolder::new(
-            config.cache_dir.as_str(), config.update_when_empty, config.load_at_start
-        ).await {
-            Ok(s) => s,
-            Err(error) => panic!("{}", error)
-        };
-        let mut server_list = vec![];
-        for server in config.server_list.iter() {
-            server_list.push(server.to_string());
+    pub async fn new_http(config: NamingConfig) -> Result<Self> {
+        let remote = HttpNamingRemote::from_config(&config).await?;
+        match Self::with_remote(config, remote.clone()).await {
+            Ok(client) => Ok(client),
+            Err(error) => {
+                remote.close();
+                Err(error)
+            }
         }
-        let remote = HttpNamingRemote::new(server_list, service_holder.clone()).await;
+    }
+
+    /// counters of received, acked and rejected udp pushes
+    pub async fn push_stats(&self) -> PushStats {
+        self.remote.push_stats().await
+    }
+}
+
+
+impl<R: NamingRemote + Clone + Send + 'static> NamingClient<R> {
+    /// a client over any remote, e.g. `InMemoryNamingRemote` of the `testing` feature in tests;
+    /// the server list of `config` is not checked, the remote gets the [ServiceUpdater] of the client first
+    pub async fn with_remote(config: NamingConfig, remote: R) -> Result<Self> {
+        config.validate_client()?;
+        let monitor = Self::monitor(&config)?;
+        let service_holder = Self::service_holder(&config, monitor.clone()).await?;
+        remote.attach(ServiceUpdater::new(service_holder.clone(), monitor.clone()));
+        Ok(Self::from_parts(config, remote, service_holder, monitor).await)
+    }
+
+    async fn from_parts(config: NamingConfig, remote: R, service_holder: ServiceHolder, monitor: Monitor) -> Self {
+        let failover_reactor = FailoverReactor::new(service_holder.clone(), config.failover_backup_interval);
         let token_holder = AccessTokenHolder::new(
-            remote.clone(), config.user_name.clone(), config.password.clone()
+            remote.clone(), config.user_name.clone(), config.password.clone(), monitor.clone()
         ).await;
-        let beat_reactor = HeartBeatReactor::new(remote.clone(), token_holder.clone());
+        let beat_reactor = HeartBeatReactor::new(remote.clone(), token_holder.clone(), monitor);
         Self {
-            config, remote, service_holder, token_holder, beat_reactor
+            config, remote, service_holder, token_holder, beat_reactor, failover_reactor,
+            owned: Mutex::new(HashMap::new()),
+            closed: AtomicBool::new(false)
         }
     }
 
-    pub async fn shutdown(&self) {
-        self.remote.shutdown().await;
+    /// deregister the owned instances selected by `opts`, stop every background task and wait for them
+    /// up to `opts.timeout`; every later call fails with [Error::Closed]
+    pub async fn shutdown(&self, opts: ShutdownOptions) -> Result<()> {
+        if self.closed.swap(true, Ordering::AcqRel) {
+            return Err(Error::Closed);
+        }
+        let deadline = Instant::now() + opts.timeout;
+        // stop the beats before deregistering, a beat in flight would register the instance again on 1.x servers
         self.beat_reactor.shutdown().await;
-        self.token_holder.shutdown()
-    }
-}
+        if tokio::time::timeout_at(deadline, self.beat_reactor.tasks().join()).await.is_err() {
+            log::warn!("beat tasks of naming client did not stop within {:?}", opts.timeout);
+        }
+        let owned = std::mem::take(&mut *self.owned.lock().await)
+            .into_values()
+            .filter(|instance| {
+                if instance.ephemeral { opts.deregister_ephemeral } else { opts.deregister_persistent }
+            })
+            .collect::<Vec<_>>();
+        let mut result = Ok(());
+        if !owned.is_empty() {
+            let namespace_id = self.config.namespace_id.as_str();
+            let token = self.token_holder.get_token().await;
+            let deregister = join_all(owned.into_iter().map(|instance| {
+                let token = token.clone();
+                async move { (instance.key(), self.remote.deregister_instance(namespace_id, token, instance).await) }
+            }));
+            result = match tokio::time::timeout_at(deadline, deregister).await {
+                Ok(results) => batch_result(results),
+                Err(_) => Err(Error::Timeout("deregister instances on shutdown".to_owned()))
+            };
+        }
 
+        self.close_tasks();
+        let join = async {
+            self.failover_reactor.tasks().join().await;
+            self.token_holder.tasks().join().await;
+            self.remote.shutdown().await;
+        };
+        if tokio::time::timeout_at(deadline, join).await.is_err() {
+            log::warn!("background tasks of naming client did not stop within {:?}", opts.timeout);
+        }
+        result
+    }
 
-impl<R: NamingRemote + Clone + Send + 'static> NamingClient<R> {
-    /// register a instance
+    /// register a instance;
+    /// ephemeral instances are kept alive by beats, persistent instances are health checked by the server
+    /// and stay registered until [NamingClient::deregister_instance] is called, even after the client is gone
     pub async fn register_instance(&self, ins: Instance) -> Result<()> {
+        self.check_open()?;
         let namespace_id = self.config.namespace_id.as_str();
         self.remote.register_instance(namespace_id, self.token_holder.get_token().await, ins.clone()).await?;
+        self.owned.lock().await.insert(owned_key(&ins), ins.clone());
+        if !ins.ephemeral {
+            return Ok(());
+        }
         self.beat_reactor.add_task(namespace_id, ins).await
     }
 
-    /// deregister a instance
+    /// deregister a instance, the only way to remove a persistent instance
     pub async fn deregister_instance(&self, instance: Instance) -> Result<()> {
+        self.check_open()?;
         let namespace_id = self.config.namespace_id.as_str();
         self.beat_reactor.remove_task(namespace_id, instance.clone()).await;
+        self.owned.lock().await.remove(owned_key(&instance).as_str());
         self.remote.deregister_instance(namespace_id, self.token_holder.get_token().await, instance).await
     }
 
+    /// register instances of one service at once; their beats are sent by a single task.
+    /// a later batch of the same service is added to the instances beating in that task.
+    /// if only some instances fail, the others stay registered and [Error::Batch] lists the failed ones
+    pub async fn batch_register_instance(
+        &self, service_name: &str, group_name: &str, mut instances: Vec<Instance>
+    ) -> Result<()> {
+        self.check_open()?;
+        let namespace_id = self.config.namespace_id.as_str();
+        let service_name = util::grouped_service_name(service_name, group_name);
+        for instance in instances.iter_mut() {
+            instance.service_name = service_name.clone();
+            instance.group_name = group_name.to_string();
+        }
+        let result = self.remote.batch_register_instance(
+            namespace_id, self.token_holder.get_token().await, instances.clone()
+        ).await;
+        let failed = match &result {
+            Ok(_) => vec![],
+            Err(Error::Batch(failed)) => failed.iter().map(|(key, _)| key.clone()).collect(),
+            Err(_) => return result
+        };
+        let registered = instances.into_iter()
+            .filter(|instance| !failed.contains(&instance.key()))
+            .collect::<Vec<_>>();
+        self.owned.lock().await.extend(
+            registered.iter().map(|instance| (owned_key(instance), instance.clone()))
+        );
+        let beating = registered.into_iter()
+            .filter(|instance| instance.ephemeral)
+            .collect::<Vec<_>>();
+        if !beating.is_empty() {
+            self.beat_reactor.add_batch_task(namespace_id, service_name.as_str(), beating).await?;
+        }
+        result
+    }
+
+    /// deregister instances of one service at once
+    pub async fn batch_deregister_instance(
+        &self, service_name: &str, group_name: &str, mut instances: Vec<Instance>
+    ) -> Result<()> {
+        self.check_open()?;
+        let namespace_id = self.config.namespace_id.as_str();
+        let service_name = util::grouped_service_name(service_name, group_name);
+        for instance in instances.iter_mut() {
+            instance.service_name = service_name.clone();
+            instance.group_name = group_name.to_string();
+        }
+        self.beat_reactor.remove_batch_task(namespace_id, instances.as_slice()).await;
+        {
+            let mut owned = self.owned.lock().await;
+            for instance in instances.iter() {
+                owned.remove(owned_key(instance).as_str());
+            }
+        }
+        self.remote.batch_deregister_instance(namespace_id, self.token_holder.get_token().await, instances).await
+    }
+
+    /// update a registered instance; later beats carry the new weight and metadata
+    pub async fn update_instance(&self, instance: Instance) -> Result<()> {
+        self.check_open()?;
+        let namespace_id = self.config.namespace_id.as_str();
+        self.remote.update_instance(namespace_id, self.token_holder.get_token().await, instance.clone()).await?;
+        if let Some(owned) = self.owned.lock().await.get_mut(owned_key(&instance).as_str()) {
+            *owned = instance.clone();
+        }
+        if instance.ephemeral && !self.beat_reactor.update_task(&instance).await {
+            log::debug!("instance[{}] has no beat task", instance.key());
+        }
+        Ok(())
+    }
+
+    /// the instance as last registered or updated by this client, none if it isn't registered by it
+    pub async fn registered_instance(&self, instance: &Instance) -> Option<Instance> {
+        self.owned.lock().await.get(owned_key(instance).as_str()).cloned()
+    }
+
+    /// configure how the server health checks the persistent instances of a cluster
+    pub async fn update_cluster(&self, cluster: Cluster) -> Result<()> {
+        self.check_open()?;
+        let namespace_id = self.config.namespace_id.as_str();
+        self.remote.update_cluster(namespace_id, self.token_holder.get_token().await, cluster).await
+    }
+
+    /// change the weight of a registered instance, `instance` is updated on success
+    pub async fn set_weight(&self, instance: &mut Instance, weight: f64) -> Result<()> {
+        let mut updated = instance.clone();
+        updated.weight = weight;
+        self.update_instance(updated.clone()).await?;
+        *instance = updated;
+        Ok(())
+    }
+
+    /// enable or disable a registered instance, `instance` is updated on success
+    pub async fn set_enabled(&self, instance: &mut Instance, enabled: bool) -> Result<()> {
+        let mut updated = instance.clone();
+        updated.enabled = enabled;
+        self.update_instance(updated.clone()).await?;
+        *instance = updated;
+        Ok(())
+    }
+
+    /// insert `upsert` into and drop `remove` from the metadata of a registered instance,
+    /// `instance` is updated on success
+    pub async fn patch_metadata(
+        &self, instance: &mut Instance, upsert: HashMap<String, String>, remove: &[&str]
+    ) -> Result<()> {
+        let mut updated = instance.clone();
+        for key in remove {
+            updated.metadata.remove(*key);
+        }
+        updated.metadata.extend(upsert);
+        self.update_instance(updated.clone()).await?;
+        *instance = updated;
+        Ok(())
+    }
+
     /// Get all instances within specified clusters of a service.
     /// auto subuscribe
     pub async fn select_instances<'a, C: AsRef<[&'a str]>>(
@@ -83,6 +333,7 @@ impl<R: NamingRemote + Clone + Send + 'static> NamingClient<R> {
         clusters: C,
         healthy: bool
     ) -> Result<Vec<Instance>> {
+        self.check_open()?;
         let namespace_id = self.config.namespace_id.as_str();
         let service_name = util::grouped_service_name(
             service_name, group_name
@@ -116,6 +367,20 @@ impl<R: NamingRemote + Clone + Send + 'static> NamingClient<R> {
         Ok(ret)
     }
 
+    /// like [NamingClient::select_instances], then filter the instances by the selector
+    /// against the labels of this client
+    pub async fn select_instances_with<'a, C: AsRef<[&'a str]>>(
+        &self,
+        service_name: &str,
+        group_name: &str,
+        clusters: C,
+        healthy: bool,
+        selector: &Selector
+    ) -> Result<Vec<Instance>> {
+        let hosts = self.select_instances(service_name, group_name, clusters, healthy).await?;
+        Ok(selector.select(&self.config.labels, hosts))
+    }
+
     /// Subscribe service to receive events of instances alteration.
     pub async fn subscribe<'a, C: AsRef<[&'a str]>, L: ServiceChangeListener + 'static>(
         &self,
@@ -124,16 +389,19 @@ impl<R: NamingRemote + Clone + Send + 'static> NamingClient<R> {
         clusters: C,
         listener: L
     ) -> Result<()> {
+        self.check_open()?;
         let namespace_id = self.config.namespace_id.as_str();
         let service_name = util::grouped_service_name(
             service_name, group_name
         );
         let cluster_vec = clusters.as_ref();
-        self.remote.subscribe(namespace_id, self.token_holder.clone(), service_name.as_str(), cluster_vec).await?;
+        self.remote.subscribe(
+            namespace_id, Arc::new(self.token_holder.clone()), service_name.as_str(), cluster_vec
+        ).await?;
 
         self.service_holder.register_subscribe(
             service_name,
-            cluster_vec.into_iter().join(","), 
+            cluster_vec.iter().join(","), 
             Box::new(listener)
         ).await;
         Ok(())
@@ -146,6 +414,7 @@ impl<R: NamingRemote + Clone + Send + 'static> NamingClient<R> {
         group_name: &str,
         clusters: C
     ) -> Result<()> {
+        self.check_open()?;
         let namespace_id = self.config.namespace_id.as_str();
         let service_name = util::grouped_service_name(
             service_name, group_name
@@ -153,4 +422,19 @@ impl<R: NamingRemote + Clone + Send + 'static> NamingClient<R> {
         let cluster_vec = clusters.as_ref();
         self.remote.unsubscribe(namespace_id, self.token_holder.get_token().await, service_name.as_str(), cluster_vec).await
     }
-}
\ No newline at end of file
+}
+
+impl<R: NamingRemote> Drop for NamingClient<R> {
+    fn drop(&mut self) {
+        if self.closed.load(Ordering::Acquire) {
+            return;
+        }
+        let ephemeral = self.owned.try_lock()
+            .map(|owned| owned.values().filter(|instance| instance.ephemeral).count())
+            .unwrap_or_default();
+        log::warn!(
+            "naming client dropped without shutdown, {} ephemeral instances are left to expire", ephemeral
+        );
+        self.close_tasks();
+    }
+}
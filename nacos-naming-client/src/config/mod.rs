@@ -0,0 +1,265 @@
+use std::{collections::HashMap, fmt::Display, net::{IpAddr, Ipv6Addr}, str::FromStr, time::Duration};
+
+use serde::Deserialize;
+
+use crate::{constants, error::ConfigError};
+
+mod builder;
+pub use builder::NamingConfigBuilder;
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    scheme: String,
+    address: String,
+    context_path: String
+}
+
+/// udp push receiver settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PushConfig {
+    /// open a udp socket to receive pushes from nacos; polling only if false
+    pub enabled: bool,
+    /// local interface the udp socket binds to
+    pub bind_ip: String,
+    /// fixed udp port; a random port in [PUSH_PORT_START, PUSH_PORT_END) is chosen if none
+    pub port: Option<u16>,
+    /// how many random ports are tried before giving up
+    pub bind_retries: u16,
+    /// ip reported to nacos as `clientIP`; detected from the local interfaces if none
+    pub client_ip: Option<String>,
+    /// only accept pushes sent from the addresses of the server list;
+    /// dump requests, which return every cached service, are only answered if true
+    pub verify_source: bool
+}
+
+/// built and validated by [NamingConfigBuilder]; the fields can be read but the struct can't be
+/// constructed outside this crate
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct NamingConfig {
+    pub namespace_id: String,
+    pub cluster: String,
+    pub group: String,
+    pub server_list: Vec<ServerConfig>,
+    pub cache_dir: String,
+    pub load_at_start: bool,
+    /// accept service infos without hosts; if false an empty push never wipes cached hosts
+    pub update_when_empty: bool,
+    /// how often cached services are backed up into the failover dir
+    pub failover_backup_interval: Duration,
+    pub user_name: Option<String>,
+    pub password: Option<String>,
+    pub push: PushConfig,
+    /// labels of this client, e.g. region and zone; compared with instance metadata by [crate::Selector]
+    pub labels: HashMap<String, String>,
+    /// registry of the client metrics; no metrics are collected if none
+    #[cfg(feature = "prometheus")]
+    pub metrics_registry: Option<prometheus::Registry>
+}
+
+impl ServerConfig {
+    /// the context path is normalized without leading and trailing `/`,
+    /// a bare ipv6 address is bracketed and gets the default port
+    pub fn new(scheme: String, address: String, context_path: String) -> Self {
+        let address = match address.parse::<Ipv6Addr>() {
+            Ok(ip) => format!("[{}]:{}", ip, constants::DEFAULT_SERVER_PORT),
+            Err(_) => address
+        };
+        ServerConfig {
+            scheme,
+            address,
+            context_path: normalize_context_path(context_path.as_str())
+        }
+    }
+
+    /// parse `[scheme://]host[:port][/context_path]` like the entries of java's `serverAddr`;
+    /// the default scheme and context path apply if the string has none, the port defaults to 8848
+    pub fn parse_with(server: &str, default_scheme: &str, default_context_path: &str) -> Result<Self, ConfigError> {
+        let invalid = || ConfigError::InvalidServer(server.to_string());
+        let server = server.trim();
+        let (scheme, rest) = match server.split_once("://") {
+            Some((scheme, rest)) => (scheme.to_ascii_lowercase(), rest),
+            None => (default_scheme.to_string(), server)
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], Some(&rest[index..])),
+            None => (rest, None)
+        };
+
+        let (host, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+            let (ip, port) = bracketed.split_once(']').ok_or_else(invalid)?;
+            let ip = ip.parse::<Ipv6Addr>().map_err(|_| invalid())?;
+            let port = match port {
+                "" => None,
+                port => Some(port.strip_prefix(':').ok_or_else(invalid)?)
+            };
+            (format!("[{}]", ip), port)
+        } else if let Ok(ip) = authority.parse::<Ipv6Addr>() {
+            (format!("[{}]", ip), None)
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host.to_string(), Some(port)),
+                None => (authority.to_string(), None)
+            }
+        };
+        if host.is_empty() || host.contains(|c: char| c.is_whitespace() || "@?#,".contains(c)) {
+            return Err(invalid());
+        }
+        let port = match port {
+            Some(port) => port.parse::<u16>().map_err(|_| invalid())?,
+            None => constants::DEFAULT_SERVER_PORT
+        };
+        let context_path = path.unwrap_or(default_context_path);
+        if context_path.contains(|c: char| c.is_whitespace() || "?#".contains(c)) {
+            return Err(invalid());
+        }
+
+        let config = ServerConfig {
+            scheme,
+            address: format!("{}:{}", host, port),
+            context_path: normalize_context_path(context_path)
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+fn normalize_context_path(context_path: &str) -> String {
+    context_path.split('/').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("/")
+}
+
+impl FromStr for ServerConfig {
+    type Err = ConfigError;
+
+    fn from_str(server: &str) -> Result<Self, Self::Err> {
+        Self::parse_with(server, constants::DEFAULT_SERVER_SCHEMA, constants::DEFAULT_SERVER_CONTEXT)
+    }
+}
+
+/// `{scheme}://{address}/{context_path}`, without a trailing `/` so request paths can be appended
+impl Display for ServerConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.context_path.is_empty() {
+            write!(f, "{}://{}", self.scheme, self.address)
+        } else {
+            write!(f, "{}://{}/{}", self.scheme, self.address, self.context_path)
+        }
+    }
+}
+
+impl Default for PushConfig {
+    fn default() -> Self {
+        PushConfig {
+            enabled: true,
+            bind_ip: constants::DEFAULT_PUSH_BIND_IP.to_owned(),
+            port: None,
+            bind_retries: constants::DEFAULT_PUSH_BIND_RETRIES,
+            client_ip: None,
+            verify_source: false
+        }
+    }
+}
+
+impl PushConfig {
+    /// no udp socket is opened, service changes are only obtained by polling
+    pub fn disabled() -> Self {
+        PushConfig {
+            enabled: false,
+            ..Default::default()
+        }
+    }
+}
+
+impl NamingConfig {
+    /// defaults from [constants], see [NamingConfigBuilder]
+    pub fn builder() -> NamingConfigBuilder {
+        NamingConfigBuilder::default()
+    }
+
+    /// check the settings, so that misconfiguration fails on construction instead of on the first request
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.server_list.is_empty() {
+            return Err(ConfigError::EmptyServerList);
+        }
+        for server in self.server_list.iter() {
+            server.validate()?;
+        }
+        self.validate_client()
+    }
+
+    /// the settings besides the server list, which a custom [crate::NamingRemote] may not use
+    pub(crate) fn validate_client(&self) -> Result<(), ConfigError> {
+        if self.namespace_id.is_empty() {
+            return Err(ConfigError::InvalidValue("namespace".to_owned(), String::new()));
+        }
+        if self.group.is_empty() || self.group.contains(constants::SERVICE_INFO_SPLITER) {
+            return Err(ConfigError::InvalidValue("group".to_owned(), self.group.clone()));
+        }
+        if self.cluster.is_empty() || self.cluster.contains(',') {
+            return Err(ConfigError::InvalidValue("cluster".to_owned(), self.cluster.clone()));
+        }
+        if self.cache_dir.is_empty() {
+            return Err(ConfigError::NoCacheDir);
+        }
+        if self.failover_backup_interval.is_zero() {
+            return Err(ConfigError::InvalidValue("failover_backup_interval".to_owned(), "0".to_owned()));
+        }
+        if self.password.is_some() && self.user_name.is_none() {
+            return Err(ConfigError::InvalidValue("password".to_owned(), "set without user name".to_owned()));
+        }
+        self.push.validate()
+    }
+}
+
+impl ServerConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.scheme != "http" && self.scheme != "https" {
+            return Err(ConfigError::InvalidServer(self.to_string()));
+        }
+        if self.address.is_empty() || self.address.contains(|c: char| c == '/' || c.is_whitespace()) {
+            return Err(ConfigError::InvalidServer(self.to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl PushConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.bind_ip.parse::<IpAddr>().is_err() {
+            return Err(ConfigError::InvalidValue("push.bind_ip".to_owned(), self.bind_ip.clone()));
+        }
+        if let Some(client_ip) = self.client_ip.as_ref() {
+            if client_ip.parse::<IpAddr>().is_err() {
+                return Err(ConfigError::InvalidValue("push.client_ip".to_owned(), client_ip.clone()));
+            }
+        }
+        if self.enabled && self.port.is_none() && self.bind_retries == 0 {
+            return Err(ConfigError::InvalidValue("push.bind_retries".to_owned(), "0".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ServerConfig;
+
+    #[test]
+    fn test_parse_server() {
+        let parse = |server: &str| server.parse::<ServerConfig>().map(|config| config.to_string());
+        assert_eq!(parse("127.0.0.1").unwrap(), "http://127.0.0.1:8848/nacos");
+        assert_eq!(parse("nacos.local:80").unwrap(), "http://nacos.local:80/nacos");
+        assert_eq!(parse("HTTPS://nacos.local/ctx/").unwrap(), "https://nacos.local:8848/ctx");
+        assert_eq!(parse("http://nacos.local:8848/").unwrap(), "http://nacos.local:8848");
+        assert_eq!(parse("::1").unwrap(), "http://[::1]:8848/nacos");
+        assert_eq!(parse("[fe80::1]:9848//a//b").unwrap(), "http://[fe80::1]:9848/a/b");
+        assert!(parse("ftp://nacos.local").is_err());
+        assert!(parse("nacos.local:port").is_err());
+        assert!(parse("[::1:8848").is_err());
+        assert!(parse("").is_err());
+
+        let server = ServerConfig::new("http".to_owned(), "127.0.0.1:8848".to_owned(), "/".to_owned());
+        assert_eq!(format!("{}{}", server, "/v1/ns/instance"), "http://127.0.0.1:8848/v1/ns/instance");
+    }
+}
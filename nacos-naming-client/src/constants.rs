@@ -1,13 +1,28 @@
-pub const DEFAULT_NAMSPACE: &'static str = "public";
-pub const DEFAULT_GROUP: &'static str = "DEFAULT_GROUP";
-pub const DEFAULT_CLUSTER: &'static str = "DEFAULT";
-pub const DEFAULT_SERVER_SCHEMA: &'static str = "http";
+use std::time::Duration;
+
+pub const DEFAULT_NAMSPACE: &str = "public";
+pub const DEFAULT_GROUP: &str = "DEFAULT_GROUP";
+pub const DEFAULT_CLUSTER: &str = "DEFAULT";
+pub const DEFAULT_SERVER_SCHEMA: &str = "http";
+pub const DEFAULT_SERVER_PORT: u16 = 8848;
 /// {SERVER_SCHEMA}://ip:port/{SERVER_CONTEXT}
-pub const DEFAULT_SERVER_CONTEXT: &'static str = "nacos";
-pub const DEFAULT_FAILOVER_DIR: &'static str = "nacos/naming/failover";
-pub const SERVICE_INFO_SPLITER: &'static str = "@@";
-pub const ALL_IPS: &'static str = "000--00-ALL_IPS--00--000";
-pub const ENV_LIST_KEY: &'static str = "envList";
-pub const ALL_HOSTS: &'static str = "00-00---000-ALL_HOSTS-000---00-00";
-pub const ENV_CONFIGS: &'static str = "00-00---000-ENV_CONFIGS-000---00-00";
-pub const VIP_CLIENT_FILE: &'static str = "vipclient.properties";
\ No newline at end of file
+pub const DEFAULT_SERVER_CONTEXT: &str = "nacos";
+pub const DEFAULT_FAILOVER_DIR: &str = "nacos/naming/failover";
+pub const SERVICE_INFO_SPLITER: &str = "@@";
+pub const ALL_IPS: &str = "000--00-ALL_IPS--00--000";
+pub const ENV_LIST_KEY: &str = "envList";
+pub const ALL_HOSTS: &str = "00-00---000-ALL_HOSTS-000---00-00";
+pub const ENV_CONFIGS: &str = "00-00---000-ENV_CONFIGS-000---00-00";
+pub const VIP_CLIENT_FILE: &str = "vipclient.properties";
+pub const DEFAULT_PUSH_BIND_IP: &str = "0.0.0.0";
+pub const DEFAULT_PUSH_BIND_RETRIES: u16 = 10;
+/// udp push port is chosen randomly in [PUSH_PORT_START, PUSH_PORT_END)
+pub const PUSH_PORT_START: u16 = 54951;
+pub const PUSH_PORT_END: u16 = 55951;
+/// failover dir under the namespaced cache dir, same layout as the java client
+pub const FAILOVER_SUB_DIR: &str = "failover";
+/// failover mode is on when this file in the failover dir contains `1`
+pub const FAILOVER_SWITCH: &str = "00-00---000-VIPSRV_FAILOVER_SWITCH-000---00-00";
+pub const FAILOVER_SWITCH_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+pub const FAILOVER_INITIAL_BACKUP_DELAY: Duration = Duration::from_secs(10);
+pub const DEFAULT_FAILOVER_BACKUP_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
@@ -1,33 +1,75 @@
 use std::{collections::HashMap, sync::Arc, time::Duration};
 
+use futures::future::join_all;
 use tokio::sync::{Mutex, mpsc};
 
 use crate::{
     net::NamingRemote,
-    error::Result
+    error::Result,
+    monitor::Monitor,
+    util::TaskGroup,
+    trace
 };
 
 use super::{model::{Instance, BeatInfo, BeatRequest}, AccessTokenHolder};
 
 
-/// 心跳检测
+/// 心跳提前2秒发送, 最短间隔1秒
+const BEAT_ADVANCE_MILLIS: u64 = 2000;
+const MIN_BEAT_PERIOD: Duration = Duration::from_secs(1);
+
+fn beat_period(client_beat_interval: u64) -> Duration {
+    Duration::from_millis(client_beat_interval.saturating_sub(BEAT_ADVANCE_MILLIS)).max(MIN_BEAT_PERIOD)
+}
+
+struct BeatTask {
+    signal: mpsc::Sender<()>,
+    /// 实例更新后心跳需要携带新的权重和元数据; key: 实例的心跳key
+    beat_infos: Arc<std::sync::Mutex<HashMap<String, BeatInfo>>>
+}
+
+impl BeatTask {
+    fn contains(&self, key: &str) -> bool {
+        self.beat_infos.lock().expect("[beat] lock poisoned").contains_key(key)
+    }
+}
+
+/// 心跳检测; 单个注册的实例各自一个任务, 批量注册的实例每个服务共用一个任务
 pub struct HeartBeatReactor<R: NamingRemote> {
     remote: R,
-    task_map: Arc<Mutex<HashMap<String, mpsc::Sender<()>>>>,
-    token_holder: AccessTokenHolder<R>
+    task_map: Arc<Mutex<HashMap<String, BeatTask>>>,
+    token_holder: AccessTokenHolder<R>,
+    tasks: TaskGroup,
+    monitor: Monitor
+}
+
+impl<R: NamingRemote> HeartBeatReactor<R> {
+    pub(crate) fn tasks(&self) -> &TaskGroup {
+        &self.tasks
+    }
 }
 
 impl<R: NamingRemote + Clone + 'static> HeartBeatReactor<R> {
 
-    pub fn new(remote: R, token_holder: AccessTokenHolder<R>) -> Self {
+    pub fn new(remote: R, token_holder: AccessTokenHolder<R>, monitor: Monitor) -> Self {
         HeartBeatReactor {
             remote,
             token_holder,
-            task_map: Arc::new(Mutex::new(HashMap::new()))
+            task_map: Arc::new(Mutex::new(HashMap::new())),
+            tasks: TaskGroup::new(),
+            monitor
         }
     }
+
+    /// 上报正在发送心跳的实例数
+    fn report_size(&self, task_map: &HashMap<String, BeatTask>) {
+        let size = task_map.values()
+            .map(|task| task.beat_infos.lock().expect("[beat] lock poisoned").len())
+            .sum();
+        self.monitor.beat_size(size);
+    }
     fn build_key(instance: &Instance) -> String {
-        return format!(
+        format!(
             "{}#{}#{}", 
             instance.service_name, 
             instance.ip, 
@@ -35,71 +77,178 @@ impl<R: NamingRemote + Clone + 'static> HeartBeatReactor<R> {
         )
     }
 
+    fn build_batch_key(service_name: &str) -> String {
+        format!("batch#{}", service_name)
+    }
+
+    fn build_beat_info(instance: &Instance) -> BeatInfo {
+        BeatInfo {
+            ip: instance.ip.clone(),
+            port: instance.port,
+            weight: instance.weight,
+            service_name: instance.service_name.clone(),
+            cluster: instance.cluster_name.clone(),
+            metadata: instance.metadata.clone()
+        }
+    }
+
+    fn find_task<'a>(task_map: &'a HashMap<String, BeatTask>, key: &str) -> Option<&'a BeatTask> {
+        task_map.get(key).or_else(|| task_map.values().find(|task| task.contains(key)))
+    }
+
     pub async fn add_task(&self, namespace_id: &str, instance: Instance) -> Result<()> {
         let key = Self::build_key(&instance);
-        let mut signal_map = self.task_map.lock().await;
-        if let Some(_) = signal_map.get(key.as_str()) {
+        let mut task_map = self.task_map.lock().await;
+        let beat_info = Self::build_beat_info(&instance);
+        // 重复注册时只更新心跳信息
+        if let Some(task) = Self::find_task(&task_map, key.as_str()) {
+            task.beat_infos.lock().expect("[beat] lock poisoned").insert(key, beat_info);
             return Ok(());
         }
-        let (tx, mut rx) = mpsc::channel(1);
-        signal_map.insert(key, tx);
+        let beat_infos = HashMap::from([(key.clone(), beat_info)]);
+        let task = self.spawn(namespace_id, instance.service_name, beat_infos);
+        task_map.insert(key, task);
+        self.report_size(&task_map);
+        Ok(())
+    }
 
-        let beat_info = BeatInfo {
-            ip: instance.ip,
-            port: instance.port,
-            weight: instance.weight,
-            service_name: instance.service_name.clone(),
-            cluster: instance.cluster_name,
-            metadata: instance.metadata
-        };
-        let mut request = BeatRequest {
-            namespace_id: namespace_id.to_string(),
-            access_token: self.token_holder.get_token().await,
-            service_name: instance.service_name,
-            beat: serde_json::to_string(&beat_info).expect("beat_info can not serialize"),
-            beat_info,
-            period: Duration::from_secs(5)
-        };
+    /// 一个任务负责服务下批量注册的全部实例; 再次批量注册同一服务时合并到原有的实例中
+    pub async fn add_batch_task(&self, namespace_id: &str, service_name: &str, instances: Vec<Instance>) -> Result<()> {
+        let beat_infos = instances.iter()
+            .map(|instance| (Self::build_key(instance), Self::build_beat_info(instance)))
+            .collect::<HashMap<_, _>>();
+        let mut task_map = self.task_map.lock().await;
+        // 单独注册过的实例改由批量任务发送心跳
+        for key in beat_infos.keys() {
+            if let Some(task) = task_map.remove(key) {
+                let _ = task.signal.send(()).await;
+            }
+        }
+        let batch_key = Self::build_batch_key(service_name);
+        if let Some(task) = task_map.get(batch_key.as_str()) {
+            // 之前批量注册的实例仍然属于客户端, 需要继续发送心跳
+            task.beat_infos.lock().expect("[beat] lock poisoned").extend(beat_infos);
+            self.report_size(&task_map);
+            return Ok(());
+        }
+        let task = self.spawn(namespace_id, service_name.to_string(), beat_infos);
+        task_map.insert(batch_key, task);
+        self.report_size(&task_map);
+        Ok(())
+    }
+
+    fn spawn(&self, namespace_id: &str, service_name: String, beat_infos: HashMap<String, BeatInfo>) -> BeatTask {
+        let (tx, mut rx) = mpsc::channel(1);
+        let beat_infos = Arc::new(std::sync::Mutex::new(beat_infos));
+        let shared_infos = beat_infos.clone();
+        let namespace_id = namespace_id.to_string();
         let token_holder = self.token_holder.clone();
         let remote = self.remote.clone();
-        tokio::spawn(async move {
+        let target = service_name.clone();
+        let task = async move {
+            let mut period = Duration::from_secs(5);
             loop {
-                request.access_token = token_holder.get_token().await;
-                let res = tokio::select!{
-                    res = remote.beat(&request) => res,
+                let access_token = token_holder.get_token().await;
+                let requests = shared_infos.lock().expect("[beat] lock poisoned")
+                    .values()
+                    .map(|beat_info| BeatRequest {
+                        namespace_id: namespace_id.clone(),
+                        access_token: access_token.clone(),
+                        service_name: service_name.clone(),
+                        beat: serde_json::to_string(beat_info).expect("beat_info can not serialize"),
+                        beat_info: beat_info.clone(),
+                        period
+                    })
+                    .collect::<Vec<_>>();
+                let results = tokio::select!{
+                    results = join_all(requests.iter().map(|request| remote.beat(request))) => results,
                     _ = rx.recv() => break
                 };
-                
-                match res {
-                    Err(err) => log::error!("[beat] failed to send beat, cause: {}", err),
-                    Ok(ack) => {
-                        request.period = Duration::from_millis(ack.client_beat_interval - 2000);
+
+                for (request, res) in requests.iter().zip(results) {
+                    match res {
+                        Err(err) => log::error!(
+                            "[beat] failed to send beat of {}:{}, cause: {}",
+                            request.beat_info.ip, request.beat_info.port, err
+                        ),
+                        Ok(ack) => {
+                            period = beat_period(ack.client_beat_interval);
+                        }
                     }
                 }
                 log::debug!(
-                    "[beat] service:{} millis_period: {}; sleep....", 
-                    request.service_name, request.period.as_millis()
+                    "[beat] service:{} instances: {} millis_period: {}; sleep....", 
+                    service_name, requests.len(), period.as_millis()
                 );
-                tokio::time::sleep(request.period).await;
+                tokio::time::sleep(period).await;
             }
-        });
-        Ok(())
+        };
+        self.tasks.spawn(trace::background(task, "beat", target.as_str()));
+        BeatTask { signal: tx, beat_infos }
     }
 
-    pub async fn remove_task(&self, _: &str, instance: Instance) {
-        let key = Self::build_key(&instance);
-        if let Some(tx) = self.task_map.lock().await.remove(&key) {
-            let _ = tx.send(()).await;
+    /// 更新心跳携带的实例信息，实例没有心跳任务时返回false
+    pub async fn update_task(&self, instance: &Instance) -> bool {
+        let key = Self::build_key(instance);
+        let task_map = self.task_map.lock().await;
+        match Self::find_task(&task_map, key.as_str()) {
+            Some(task) => {
+                task.beat_infos.lock().expect("[beat] lock poisoned").insert(key, Self::build_beat_info(instance));
+                true
+            },
+            None => false
         }
     }
 
-    pub async fn shutdown(&self) {
-        let mut map = self.task_map.lock().await;
-        let txs = map.iter().collect::<Vec<_>>();
-        for (_, tx) in txs {
-            let _ = tx.send(()).await;
+    pub async fn remove_task(&self, namespace_id: &str, instance: Instance) {
+        self.remove_batch_task(namespace_id, &[instance]).await
+    }
+
+    /// 停止实例的心跳，批量任务中的实例全部移除后任务结束
+    pub async fn remove_batch_task(&self, _: &str, instances: &[Instance]) {
+        let mut task_map = self.task_map.lock().await;
+        for instance in instances {
+            let key = Self::build_key(instance);
+            let task_key = task_map.iter()
+                .find(|(task_key, task)| **task_key == key || task.contains(key.as_str()))
+                .map(|(task_key, _)| task_key.clone());
+            let task_key = match task_key {
+                Some(task_key) => task_key,
+                None => continue
+            };
+            let empty = {
+                let task = &task_map[task_key.as_str()];
+                let mut beat_infos = task.beat_infos.lock().expect("[beat] lock poisoned");
+                beat_infos.remove(key.as_str());
+                beat_infos.is_empty()
+            };
+            if empty {
+                if let Some(task) = task_map.remove(task_key.as_str()) {
+                    let _ = task.signal.send(()).await;
+                }
+            }
         }
-        
-        map.clear();
+        self.report_size(&task_map);
+    }
+
+    /// stop every beat task
+    pub async fn shutdown(&self) {
+        self.tasks.close();
+        self.task_map.lock().await.clear();
+        self.monitor.beat_size(0);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::beat_period;
+
+    #[test]
+    fn test_beat_period() {
+        assert_eq!(beat_period(5000), Duration::from_secs(3));
+        assert_eq!(beat_period(2500), Duration::from_secs(1));
+        assert_eq!(beat_period(0), Duration::from_secs(1));
+    }
+}
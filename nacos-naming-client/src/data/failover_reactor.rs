@@ -0,0 +1,92 @@
+use std::{collections::HashMap, path::PathBuf, time::{Duration, SystemTime}};
+
+use crate::{constants, util::TaskGroup};
+
+use super::{model::ServiceInfo, ServiceHolder};
+
+
+/// 故障转移: 监听failover开关文件，开关打开时只使用failover目录中的数据；
+/// 并定期把内存中的服务数据备份到failover目录
+pub struct FailoverReactor {
+    tasks: TaskGroup
+}
+
+impl FailoverReactor {
+    pub fn new(holder: ServiceHolder, backup_interval: Duration) -> Self {
+        let tasks = TaskGroup::new();
+        tasks.spawn(refresh_switch(holder.clone()));
+        tasks.spawn(backup(holder, backup_interval));
+        FailoverReactor { tasks }
+    }
+
+    pub(crate) fn tasks(&self) -> &TaskGroup {
+        &self.tasks
+    }
+}
+
+async fn refresh_switch(holder: ServiceHolder) {
+    let switch_file = holder.failover_dir().join(constants::FAILOVER_SWITCH);
+    let mut last_modified: Option<SystemTime> = None;
+    loop {
+        let modified = tokio::fs::metadata(&switch_file).await
+            .and_then(|meta| meta.modified())
+            .ok();
+        if modified != last_modified {
+            last_modified = modified;
+            let switch_on = match tokio::fs::read_to_string(&switch_file).await {
+                Ok(content) => content.trim() == "1",
+                Err(_) => false
+            };
+            if switch_on {
+                let failover = load_failover(holder.failover_dir()).await;
+                log::warn!("[failover] switch on, serve {} services from failover dir", failover.len());
+                holder.switch_failover(Some(failover)).await;
+            } else if holder.is_failover().await {
+                log::warn!("[failover] switch off");
+                holder.switch_failover(None).await;
+            }
+        }
+
+        tokio::time::sleep(constants::FAILOVER_SWITCH_REFRESH_INTERVAL).await;
+    }
+}
+
+async fn load_failover(dir: PathBuf) -> HashMap<String, ServiceInfo> {
+    let map = nacos_sdk_core::cache::read_dir_filter::<ServiceInfo>(
+        dir, |file_name| file_name != constants::FAILOVER_SWITCH
+    ).await;
+    match map {
+        Ok(map) => map.into_values().map(|info| (info.get_key(), info)).collect(),
+        Err(error) => {
+            log::error!("[failover] failed to read failover dir: {}", error);
+            HashMap::new()
+        }
+    }
+}
+
+async fn backup(holder: ServiceHolder, interval: Duration) {
+    let dir = holder.failover_dir();
+    // failover目录为空时尽快做第一次备份
+    let mut delay = match nacos_sdk_core::cache::read_dir_str(dir.as_path()).await {
+        Ok(files) if files.keys().any(|name| name != constants::FAILOVER_SWITCH) => interval,
+        _ => constants::FAILOVER_INITIAL_BACKUP_DELAY.min(interval)
+    };
+    loop {
+        tokio::time::sleep(delay).await;
+        delay = interval;
+        // 故障转移期间failover目录中的数据可能是运维手动维护的，不能覆盖
+        if holder.is_failover().await {
+            continue
+        }
+        let service_map = holder.get_service_info_map().await;
+        for info in service_map.values() {
+            let result = nacos_sdk_core::cache::write_file(
+                info, dir.as_path(), info.get_key_encoded().as_str()
+            ).await;
+            if let Err(error) = result {
+                log::warn!("[failover] failed to backup service[{}]: {}", info.service_name, error);
+            }
+        }
+        log::debug!("[failover] {} services have been backed up", service_map.len());
+    }
+}
@@ -2,15 +2,47 @@ pub mod model;
 mod security;
 mod beat_reactor;
 mod service_holder;
+mod failover_reactor;
 
 pub use beat_reactor::HeartBeatReactor;
 pub use service_holder::ServiceHolder;
+pub(crate) use service_holder::migrate_legacy_cache;
+pub use failover_reactor::FailoverReactor;
 pub use security::AccessTokenHolder;
-use self::model::Instance;
+use self::model::{Instance, ServiceInfo};
+use crate::monitor::Monitor;
 
 use async_trait::async_trait;
 
 #[async_trait]
 pub trait ServiceChangeListener: Send + Sync {
     async fn changed(&self, service_name: &str, hosts: Vec<Instance>);
-}
\ No newline at end of file
+}
+
+/// 客户端服务缓存的更新入口，NamingRemote把推送或轮询到的服务信息交给它，由它通知监听者
+#[derive(Clone)]
+pub struct ServiceUpdater {
+    holder: ServiceHolder,
+    monitor: Monitor
+}
+
+impl ServiceUpdater {
+    pub(crate) fn new(holder: ServiceHolder, monitor: Monitor) -> Self {
+        ServiceUpdater { holder, monitor }
+    }
+
+    pub(crate) fn holder(&self) -> &ServiceHolder {
+        &self.holder
+    }
+
+    /// the metrics of the client, for the requests of the remote
+    pub(crate) fn monitor(&self) -> &Monitor {
+        &self.monitor
+    }
+
+    /// cache `info` as an answer of the server and notify its listeners before returning;
+    /// false if it is empty while `update_when_empty` is off, and was ignored
+    pub async fn update(&self, info: ServiceInfo) -> bool {
+        self.holder.update_service_info(info).await
+    }
+}
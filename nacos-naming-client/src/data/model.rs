@@ -9,7 +9,7 @@ fn bool_default() -> bool {
 }
 
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")] 
 pub struct BeatInfo {
     pub ip: String,
@@ -82,6 +82,43 @@ pub struct Instance {
     pub metadata: HashMap<String, String>
 }
 
+/// server side health check of the persistent instances in a cluster
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum HealthChecker {
+    /// instances are only marked by explicit health updates
+    #[serde(rename = "NONE")]
+    None,
+    #[serde(rename = "TCP")]
+    Tcp,
+    #[serde(rename = "HTTP", rename_all = "camelCase")]
+    Http {
+        path: String,
+        /// `key:value` pairs separated by `|`
+        headers: String,
+        expected_response_code: u16
+    },
+    #[serde(rename = "MYSQL")]
+    Mysql {
+        user: String,
+        pwd: String,
+        cmd: String
+    }
+}
+
+/// cluster settings of a service, used by the server to health check persistent instances
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    /// Service information of cluster, include group: {group}@@{name}
+    pub service_name: String,
+    pub cluster_name: String,
+    pub health_checker: HealthChecker,
+    /// port checked when `use_instance_port` is false
+    pub check_port: u16,
+    pub use_instance_port: bool,
+    pub metadata: HashMap<String, String>
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")] 
 pub struct ServiceInfo {
@@ -109,8 +146,8 @@ pub struct BeatAck {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")] 
 pub struct ServiceList {
-    count: u64,
-    doms: Vec<String>
+    pub count: u64,
+    pub doms: Vec<String>
 }
 
 impl Default for Token {
@@ -120,11 +157,17 @@ impl Default for Token {
 }
 
 impl Token {
+    /// servers may be configured with any ttl, only the empty default token is invalid
     pub fn valid(&self) -> bool {
-        self.token_ttl > 10000
+        !self.access_token.is_empty() && self.token_ttl > 0
     }
 }
 
+/// the instance of `list` with the same `ip:port` as `instance`
+pub(crate) fn get_same_instance<'a>(list: &'a [Instance], instance: &Instance) -> Option<&'a Instance> {
+    list.iter().find(|item| item.key() == instance.key())
+}
+
 impl Instance {
     pub fn key(&self) -> String {
         format!("{}:{}", self.ip, self.port)
@@ -158,11 +201,38 @@ impl Instance {
         }
     }
 
+    /// a persistent instance: no client beats, the server health checks it
+    /// and it stays registered until deregistered explicitly
+    pub fn new_persistent(
+        service_name: &str,
+        group_name: &str,
+        cluster_name: &str,
+        ip: &str, port: u16
+    ) -> Instance {
+        Instance {
+            ephemeral: false,
+            ..Self::new_with_required(service_name, group_name, cluster_name, ip, port)
+        }
+    }
+
     pub fn get_id_uncheck(&self) -> &str {
         self.id.as_ref().expect("instance id is empty!").as_str()
     }
 }
 
+impl Cluster {
+    pub fn new(service_name: &str, group_name: &str, cluster_name: &str, health_checker: HealthChecker) -> Self {
+        Cluster {
+            service_name: util::grouped_service_name(service_name, group_name),
+            cluster_name: cluster_name.to_string(),
+            health_checker,
+            check_port: 80,
+            use_instance_port: true,
+            metadata: HashMap::new()
+        }
+    }
+}
+
 impl ServiceInfo {
 
     pub fn get_key(&self) -> String {
@@ -170,6 +240,26 @@ impl ServiceInfo {
         Self::generate_key(self.service_name.as_str(), self.clusters.as_str())
     }
 
+    pub fn empty(name: &str, clusters: &str) -> ServiceInfo {
+        ServiceInfo {
+            service_name: name.to_string(),
+            clusters: clusters.to_string(),
+            cache_millis: 0,
+            hosts: vec![],
+            last_ref_time: 0,
+            checksum: "".to_string(),
+            all_ips: false,
+            reach_protection_threshold: false
+        }
+    }
+
+    /// cache file name of the service, same as java's `ServiceInfo.getKeyEncoded`
+    pub fn get_key_encoded(&self) -> String {
+        // like java only the grouped name is encoded, the clusters are kept as they are
+        let name = nacos_sdk_core::cache::encode_file_name(self.service_name.as_str());
+        Self::generate_key(name.as_str(), self.clusters.as_str())
+    }
+
     pub fn generate_key(name: &str, clusters: &str) -> String {
         if !clusters.is_empty() {
             format!("{}{}{}", name, constants::SERVICE_INFO_SPLITER, clusters)
@@ -194,4 +284,28 @@ impl ServiceInfo {
 
         sub > Duration::from_millis(self.cache_millis)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HealthChecker, ServiceInfo};
+
+    #[test]
+    fn test_key_encoded() {
+        // same file name as java's ServiceInfo.getKeyEncoded
+        let info = ServiceInfo::empty("DEFAULT_GROUP@@svc", "a,b");
+        assert_eq!(info.get_key_encoded(), "DEFAULT_GROUP%40%40svc@@a,b");
+    }
+
+    #[test]
+    fn test_health_checker_json() {
+        assert_eq!(serde_json::to_string(&HealthChecker::Tcp).unwrap(), r#"{"type":"TCP"}"#);
+        let http = HealthChecker::Http {
+            path: "/health".to_owned(), headers: String::new(), expected_response_code: 200
+        };
+        assert_eq!(
+            serde_json::to_string(&http).unwrap(),
+            r#"{"type":"HTTP","path":"/health","headers":"","expectedResponseCode":200}"#
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use std::{sync::Arc, time::Duration};
 
-use tokio::sync::{Mutex, broadcast};
+use async_trait::async_trait;
+use tokio::sync::Mutex;
 
-use crate::net::NamingRemote;
+use crate::{net::{NamingRemote, TokenSource}, monitor::Monitor, util::TaskGroup};
 
 use super::model::Token;
 
@@ -12,7 +13,8 @@ pub struct AccessTokenHolder<R:NamingRemote + Sized> {
     password: Option<String>,
     remote: R,
     token: Arc<Mutex<Token>>,
-    shutdown: broadcast::Sender<()>
+    tasks: TaskGroup,
+    monitor: Monitor
 }
 
 impl<R: NamingRemote> AccessTokenHolder<R> {
@@ -24,19 +26,22 @@ impl<R: NamingRemote> AccessTokenHolder<R> {
             None
         }
     }
-    pub fn shutdown(&self) {
-        let _ = self.shutdown.send(());
+    pub(crate) fn tasks(&self) -> &TaskGroup {
+        &self.tasks
+    }
+}
+
+#[async_trait]
+impl<R: NamingRemote> TokenSource for AccessTokenHolder<R> {
+    async fn get_token(&self) -> Option<String> {
+        AccessTokenHolder::get_token(self).await
     }
 }
 
 impl<R: NamingRemote + Send + Clone + 'static> AccessTokenHolder<R> {
-    pub async fn new(remote: R, user_name: Option<String>, password: Option<String>) -> Self {
-        let (tx, _) = broadcast::channel(1);
-        let token = if user_name.is_some() && password.is_some() {
-            let maybe_token = remote.login(
-                user_name.as_ref().expect("[security] never happen").as_str(), 
-                password.as_ref().expect("[security] never happen").as_str()
-            ).await;
+    pub async fn new(remote: R, user_name: Option<String>, password: Option<String>, monitor: Monitor) -> Self {
+        let token = if let (Some(user_name), Some(password)) = (user_name.as_ref(), password.as_ref()) {
+            let maybe_token = remote.login(user_name.as_str(), password.as_str()).await;
             match maybe_token {
                 Ok(token) => token,
                 Err(error) => {
@@ -53,8 +58,9 @@ impl<R: NamingRemote + Send + Clone + 'static> AccessTokenHolder<R> {
             user_name,
             password,
             token: token_holder,
-            shutdown: tx,
-            remote
+            tasks: TaskGroup::new(),
+            remote,
+            monitor
         };
         
         holder.start();
@@ -66,10 +72,10 @@ impl<R: NamingRemote + Send + Clone + 'static> AccessTokenHolder<R> {
         if self.user_name.is_none() || self.password.is_none() {
             return
         }
-        tokio::spawn(do_task(
+        self.tasks.spawn(do_task(
             self.token.clone(),
-            self.shutdown.subscribe(), 
             self.remote.clone(), 
+            self.monitor.clone(),
             self.user_name.clone().expect("[token:userName]never happen"), 
             self.password.clone().expect("[token:password]never happen")
         ));
@@ -78,19 +84,17 @@ impl<R: NamingRemote + Send + Clone + 'static> AccessTokenHolder<R> {
 
 async fn do_task(
     token_holder: Arc<Mutex<Token>>, 
-    mut rx: broadcast::Receiver<()>, 
     remote: impl NamingRemote, 
+    monitor: Monitor,
     user_name: String, password: String
 ) {
     loop {
-        let res = tokio::select!{
-            res = remote.login(user_name.as_str(), password.as_str()) => res,
-            _ = rx.recv() => break
-        };
-        
+        let res = remote.login(user_name.as_str(), password.as_str()).await;
+
         let ttl = match res {
             Err(err) => {
                 log::error!("[token] failed to obtain token, try later; cause: {}", err);
+                monitor.token_refresh_failed();
                 6u64
             },
             Ok(token) => {
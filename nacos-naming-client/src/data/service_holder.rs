@@ -3,23 +3,30 @@ use std::{collections::HashMap, sync::Arc, path::{Path, PathBuf}};
 use itertools::Itertools;
 use tokio::sync::Mutex;
 
-use crate::{error::{Error, Result}, model::Instance};
+use crate::{constants, error::{Error, Result}, model::Instance, monitor::Monitor};
 
-use super::{model::ServiceInfo, ServiceChangeListener};
+use super::{model::{ServiceInfo, get_same_instance}, ServiceChangeListener};
 
 
+type ListenerMap = HashMap<String, Vec<Box<dyn ServiceChangeListener>>>;
+
 /// 服务缓存
 #[derive(Clone)]
 pub struct ServiceHolder {
     service_map: Arc<Mutex<HashMap<String, ServiceInfo>>>,
-    callbacks: Arc<Mutex<HashMap<String, Vec<Box<dyn ServiceChangeListener>>>>>,
+    /// 故障转移开关打开时，只从failover目录加载的数据中提供服务
+    failover_map: Arc<Mutex<Option<HashMap<String, ServiceInfo>>>>,
+    callbacks: Arc<Mutex<ListenerMap>>,
     cache_dir: PathBuf,
-    update_when_empty: bool
+    update_when_empty: bool,
+    monitor: Monitor
 }
 
 impl ServiceHolder {
 
-    pub async fn new(cache_dir: impl AsRef<Path>, update_when_empty: bool, load_at_start: bool) -> Result<Self> {
+    pub async fn new(
+        cache_dir: impl AsRef<Path>, update_when_empty: bool, load_at_start: bool, monitor: Monitor
+    ) -> Result<Self> {
         let path = cache_dir.as_ref();
         if !path.exists() {
             tokio::fs::create_dir_all(path).await
@@ -28,13 +35,17 @@ impl ServiceHolder {
 
         let holder = ServiceHolder {
             service_map: Arc::new(Mutex::new(HashMap::new())),
+            failover_map: Arc::new(Mutex::new(None)),
             callbacks: Arc::new(Mutex::new(HashMap::new())),
             cache_dir: cache_dir.as_ref().to_path_buf(),
-            update_when_empty
+            update_when_empty,
+            monitor
         };
 
         if load_at_start {
-            holder.load_from_disk().await?;
+            if let Err(error) = holder.load_from_disk().await {
+                log::error!("failed to load service_info cache from disk: {}", error);
+            }
         }
 
         Ok(holder)
@@ -44,44 +55,115 @@ impl ServiceHolder {
         let dir = self.cache_dir.as_path();
         let map: HashMap<String, ServiceInfo> = nacos_sdk_core::cache::read_dir(dir).await?;
 
+        // file names are encoded, the key is rebuilt from the content
         let mut info_map = self.service_map.lock().await;
-        info_map.extend(map.into_iter());
+        info_map.extend(map.into_values().map(|info| (info.get_key(), info)));
+        self.monitor.service_info_map_size(info_map.len());
         Ok(())
     }
 
+    pub fn failover_dir(&self) -> PathBuf {
+        self.cache_dir.join(constants::FAILOVER_SUB_DIR)
+    }
+
+    pub async fn is_failover(&self) -> bool {
+        self.failover_map.lock().await.is_some()
+    }
+
+    /// in failover mode a service missing from the failover dir has no instances
     pub async fn get_service_info(
         &self,
         service_name: &str,
         clusters: &[&str]
     ) -> Option<ServiceInfo> {
-        let clusters = clusters.into_iter().join(",");
+        let clusters = clusters.iter().join(",");
         let key = ServiceInfo::generate_key(service_name, clusters.as_str());
-        self.service_map.lock().await.get(key.as_str()).map(|data| data.clone())
+        if let Some(failover_map) = self.failover_map.lock().await.as_ref() {
+            return Some(failover_map.get(key.as_str()).cloned().unwrap_or_else(|| ServiceInfo::empty(
+                service_name, clusters.as_str()
+            )));
+        }
+        self.service_map.lock().await.get(key.as_str()).cloned()
     }
 
-    pub async fn update_service_info(
-        &self,
-        service_info: ServiceInfo
-    ) {
+    /// cache an answer of the server, e.g. a poll; it replaces the cached info whatever its ref time,
+    /// so a forged push never blocks the real updates. returns false if it has no hosts
+    /// while update_when_empty is off, and was ignored
+    pub async fn update_service_info(&self, service_info: ServiceInfo) -> bool {
+        self.update(service_info, false).await
+    }
+
+    /// cache a pushed service info; if `ordered` it is ignored unless newer than the cached info
+    pub async fn update_pushed_info(&self, service_info: ServiceInfo, ordered: bool) -> bool {
+        self.update(service_info, ordered).await
+    }
+
+    async fn update(&self, service_info: ServiceInfo, ordered: bool) -> bool {
         let key = service_info.get_key();
-        let old = self.service_map.lock().await.insert(key.clone(), service_info.clone());
-        let push_instance = Self::diff_instance(old.map(|x|x.hosts), service_info.hosts.clone());
-        let mut callbacks = self.callbacks.lock().await;
-        let maybe_callbacks = callbacks.get_mut(key.as_str());
-        let mut default = vec![];
-        let vec = maybe_callbacks.unwrap_or(&mut default);
-        for listener in vec {
-            listener.changed(key.as_str(), push_instance.clone()).await;
-            log::debug!("service change has been notified: {}", key.as_str());
+        let old = {
+            let mut service_map = self.service_map.lock().await;
+            if let Some(cached) = service_map.get(key.as_str()) {
+                if ordered && cached.last_ref_time >= service_info.last_ref_time {
+                    log::warn!(
+                        "out of date service info received, key: {}, cached: {}, received: {}",
+                        key, cached.last_ref_time, service_info.last_ref_time
+                    );
+                    return false;
+                }
+                if !self.update_when_empty && service_info.hosts.is_empty() && !cached.hosts.is_empty() {
+                    log::warn!("empty service info received, keep the cached hosts: {}", key);
+                    return false;
+                }
+            }
+            let old = service_map.insert(key.clone(), service_info.clone());
+            self.monitor.service_info_map_size(service_map.len());
+            old
+        };
+        // 故障转移期间只缓存数据，不通知监听者
+        if !self.is_failover().await {
+            self.notify(key.as_str(), old.map(|x| x.hosts), service_info.hosts.clone()).await;
         }
         let result = nacos_sdk_core::cache::write_file(
-            &service_info, self.cache_dir.clone(), key.as_str()
+            &service_info, self.cache_dir.clone(), service_info.get_key_encoded().as_str()
         ).await;
 
         if let Err(error) = result {
             log::warn!("can not write service_info cache to disk: {}", error);
         }
         log::debug!("service change has write to disk successed: {}", service_info.service_name);
+        true
+    }
+
+    /// switch failover mode on with the data loaded from the failover dir, or off with None;
+    /// listeners are notified with the data they are served from now on
+    pub async fn switch_failover(&self, failover: Option<HashMap<String, ServiceInfo>>) {
+        let previous = {
+            let mut failover_map = self.failover_map.lock().await;
+            std::mem::replace(&mut *failover_map, failover.clone())
+        };
+        let service_map = self.service_map.lock().await.clone();
+        let (old_map, new_map) = match failover {
+            Some(failover) => (previous.unwrap_or(service_map), failover),
+            None => (previous.unwrap_or_default(), service_map)
+        };
+        let keys = self.callbacks.lock().await.keys().cloned().collect::<Vec<_>>();
+        for key in keys {
+            let old = old_map.get(key.as_str()).map(|info| info.hosts.clone());
+            let new = new_map.get(key.as_str()).map(|info| info.hosts.clone()).unwrap_or_default();
+            self.notify(key.as_str(), old, new).await;
+        }
+    }
+
+    async fn notify(&self, key: &str, old: Option<Vec<Instance>>, new: Vec<Instance>) {
+        let push_instance = Self::diff_instance(old, new);
+        let mut callbacks = self.callbacks.lock().await;
+        let maybe_callbacks = callbacks.get_mut(key);
+        let mut default = vec![];
+        let vec = maybe_callbacks.unwrap_or(&mut default);
+        for listener in vec {
+            listener.changed(key, push_instance.clone()).await;
+            log::debug!("service change has been notified: {}", key);
+        }
     }
 
     fn diff_instance(old: Option<Vec<Instance>>, mut new: Vec<Instance>) -> Vec<Instance> {
@@ -94,38 +176,34 @@ impl ServiceHolder {
         }
 
         for mut old_instance in old {
-            match Self::get_same_instance(&new, &old_instance) {
-                // 这里没有剔除已经在本地生效的instance
-                // 调用者应该做去重处理来避免把该instance的权重错误加重
-                None => {
-                    // 如果new中没有该instance但是old中有，那么应该设置为剔除
-                    old_instance.enabled = false;
-                    new.push(old_instance);
-                },
-                // 其他情况不处理
-                _ => {}
-            };
+            // 这里没有剔除已经在本地生效的instance
+            // 调用者应该做去重处理来避免把该instance的权重错误加重
+            // 如果new中没有该instance但是old中有，那么应该设置为剔除
+            if get_same_instance(&new, &old_instance).is_none() {
+                old_instance.enabled = false;
+                new.push(old_instance);
+            }
         }
 
         new
     }
 
-    fn get_same_instance<'a>(new: &'a Vec<Instance>, old: &'a Instance) -> Option<&'a Instance> {
-        for item in new.iter() {
-            if item.key() == old.key() {
-                return Some(item)
-            }
-        }
-        None
-    }
-
+    /// the listener is first notified with the instances served now, e.g. cached by the subscribe call of the remote
     pub async fn register_subscribe(
         &self, 
         service_name: String, clusters: String, 
         listener: Box<dyn ServiceChangeListener>
     ) {
         let key = ServiceInfo::generate_key(service_name.as_str(), clusters.as_str());
+        // an update cached from now on waits for the lock to notify, and reaches the listener too
         let mut callback_map = self.callbacks.lock().await;
+        let served = match self.failover_map.lock().await.as_ref() {
+            Some(failover_map) => failover_map.get(key.as_str()).map(|info| info.hosts.clone()),
+            None => self.service_map.lock().await.get(key.as_str()).map(|info| info.hosts.clone())
+        };
+        if let Some(hosts) = served.filter(|hosts| !hosts.is_empty()) {
+            listener.changed(key.as_str(), hosts).await;
+        }
         if !callback_map.contains_key(key.as_str()) {
             callback_map.insert(key.clone(), vec![]);
         }
@@ -138,3 +216,108 @@ impl ServiceHolder {
     }
 }
 
+/// written into the cache dir once the legacy cache files have been migrated
+const LEGACY_MIGRATED: &str = ".legacy-migrated";
+
+/// earlier versions kept the cache files directly in the cache dir without telling the namespaces apart;
+/// they are copied once into the dir of the default namespace, the only one they can be attributed to.
+/// the old files stay for clients which were not upgraded
+pub(crate) async fn migrate_legacy_cache(root: &Path, namespace_id: &str) {
+    let marker = root.join(LEGACY_MIGRATED);
+    if namespace_id != constants::DEFAULT_NAMSPACE || marker.exists() {
+        return;
+    }
+    let legacy = match nacos_sdk_core::cache::read_dir_filter::<ServiceInfo>(root, |_| true).await {
+        Ok(legacy) if !legacy.is_empty() => legacy,
+        _ => return
+    };
+    let dir = root.join(namespace_id);
+    log::info!("copy {} cached services from {:?} into {:?}", legacy.len(), root, dir);
+    for info in legacy.into_values() {
+        let file_name = info.get_key_encoded();
+        // never replace what an upgraded client has cached already
+        if dir.join(file_name.as_str()).exists() {
+            continue;
+        }
+        if let Err(error) = nacos_sdk_core::cache::write_file(&info, dir.as_path(), file_name.as_str()).await {
+            log::warn!("can not copy service_info cache of {}: {}", info.service_name, error);
+        }
+    }
+    if let Err(error) = nacos_sdk_core::cache::write_file_str("", root, LEGACY_MIGRATED).await {
+        log::warn!("can not mark the legacy cache as migrated: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::model::{Instance, ServiceInfo};
+
+    use super::ServiceHolder;
+
+    fn service_info(hosts: Vec<Instance>, last_ref_time: u64) -> ServiceInfo {
+        let mut info = ServiceInfo::empty("DEFAULT_GROUP@@test", "DEFAULT");
+        info.hosts = hosts;
+        info.last_ref_time = last_ref_time;
+        info
+    }
+
+    #[tokio::test]
+    async fn test_empty_protection_and_failover() {
+        let dir = std::env::temp_dir().join(format!("nacos-holder-{}", std::process::id()));
+        let holder = ServiceHolder::new(&dir, false, false, Default::default()).await.unwrap();
+        let host = Instance::new_with_defaults("test", "127.0.0.1", 8080);
+
+        assert!(holder.update_service_info(service_info(vec![host.clone()], 1)).await);
+        assert!(!holder.update_service_info(service_info(vec![], 2)).await, "empty push is ignored");
+        assert!(!holder.update_pushed_info(service_info(vec![host.clone()], 0), true).await, "stale push is ignored");
+        assert!(!holder.update_pushed_info(service_info(vec![host.clone()], 1), true).await, "replayed push is ignored");
+        assert!(holder.update_service_info(service_info(vec![host], 0)).await, "polls are always applied");
+        let info = holder.get_service_info("DEFAULT_GROUP@@test", &["DEFAULT"]).await.unwrap();
+        assert_eq!(info.hosts.len(), 1);
+
+        let mut failover = HashMap::new();
+        let failover_info = service_info(vec![Instance::new_with_defaults("test", "127.0.0.2", 8080)], 1);
+        failover.insert(failover_info.get_key(), failover_info);
+        holder.switch_failover(Some(failover)).await;
+        let info = holder.get_service_info("DEFAULT_GROUP@@test", &["DEFAULT"]).await.unwrap();
+        assert_eq!(info.hosts[0].ip, "127.0.0.2");
+        let info = holder.get_service_info("DEFAULT_GROUP@@other", &["DEFAULT"]).await.unwrap();
+        assert!(info.hosts.is_empty(), "only failover data is served");
+
+        holder.switch_failover(None).await;
+        let info = holder.get_service_info("DEFAULT_GROUP@@test", &["DEFAULT"]).await.unwrap();
+        assert_eq!(info.hosts[0].ip, "127.0.0.1");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_legacy_cache() {
+        let root = std::env::temp_dir().join(format!("nacos-holder-legacy-{}", std::process::id()));
+        let info = service_info(vec![Instance::new_with_defaults("test", "127.0.0.1", 8080)], 1);
+        nacos_sdk_core::cache::write_file_str(
+            serde_json::to_string(&info).unwrap().as_str(), &root, "DEFAULT_GROUP@@test@@DEFAULT"
+        ).await.unwrap();
+
+        // the legacy files can't be told apart by namespace, only the default one gets them
+        super::migrate_legacy_cache(&root, "other").await;
+        assert!(!root.join("other").exists());
+
+        let dir = root.join("public");
+        super::migrate_legacy_cache(&root, "public").await;
+        let holder = ServiceHolder::new(&dir, false, true, Default::default()).await.unwrap();
+        let info = holder.get_service_info("DEFAULT_GROUP@@test", &["DEFAULT"]).await.unwrap();
+        assert_eq!(info.hosts.len(), 1);
+        assert!(dir.join("DEFAULT_GROUP%40%40test@@DEFAULT").exists());
+        assert!(root.join("DEFAULT_GROUP@@test@@DEFAULT").exists(), "legacy files are kept");
+
+        // migrated once, a removed copy is not brought back
+        std::fs::remove_file(dir.join("DEFAULT_GROUP%40%40test@@DEFAULT")).unwrap();
+        super::migrate_legacy_cache(&root, "public").await;
+        assert!(!dir.join("DEFAULT_GROUP%40%40test@@DEFAULT").exists());
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -1,21 +1,140 @@
+use itertools::Itertools;
 use reqwest::StatusCode;
 use serde_repr::*;
 use thiserror::Error;
 
-#[derive(Debug, Serialize_repr, Deserialize_repr)]
+/// business codes returned by nacos servers, the first three by the v1 naming api,
+/// the others are the error codes of the v2 open api
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
 #[repr(u32)]
 pub enum RespCode {
     Ok = 10200,
     ResourceNotFound = 20404,
-    NoNeedRetry = 21600
+    NoNeedRetry = 21600,
+
+    Success = 0,
+    ParameterMissing = 10000,
+    AccessDenied = 10001,
+    DataAccessError = 10002,
+    TenantParamError = 20001,
+    ParameterValidateError = 20002,
+    MediaTypeError = 20003,
+    ResourceNotFoundV2 = 20004,
+    ResourceConflict = 20005,
+    ConfigListenerIsNull = 20006,
+    ConfigListenerError = 20007,
+    InvalidDataId = 20008,
+    ParameterMismatch = 20009,
+    ServiceNameError = 21000,
+    WeightError = 21001,
+    InstanceMetadataError = 21002,
+    InstanceNotFound = 21003,
+    InstanceError = 21004,
+    ServiceMetadataError = 21005,
+    SelectorError = 21006,
+    ServiceAlreadyExist = 21007,
+    ServiceNotExist = 21008,
+    ServiceDeleteFailure = 21009,
+    HealthyParamMiss = 21010,
+    HealthCheckStillRunning = 21011,
+    IllegalNamespace = 22000,
+    NamespaceNotExist = 22001,
+    NamespaceAlreadyExist = 22002,
+    IllegalState = 23000,
+    NodeInfoError = 23001,
+    NodeDownFailure = 23002,
+    ServerError = 30000,
+    ApiDeprecated = 40000
+}
+
+impl RespCode {
+    /// a later call may succeed without changing the request
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            RespCode::DataAccessError | RespCode::HealthCheckStillRunning | RespCode::IllegalState
+                | RespCode::NodeDownFailure | RespCode::ServerError
+        )
+    }
+}
+
+/// the codes are read through the `Deserialize_repr` table, unknown codes are returned as the error
+impl TryFrom<u32> for RespCode {
+    type Error = u32;
+
+    fn try_from(code: u32) -> std::result::Result<Self, u32> {
+        use serde::{Deserialize, de::{IntoDeserializer, value}};
+        RespCode::deserialize(IntoDeserializer::<value::Error>::into_deserializer(code)).map_err(|_| code)
+    }
+}
+
+/// a non 200 response of a nacos server
+#[derive(Debug)]
+pub struct ServerError {
+    pub status: StatusCode,
+    /// the `code` field of a json body, only sent by the v2 api
+    pub code: Option<u32>,
+    pub message: String
+}
+
+impl ServerError {
+    pub fn new(status: StatusCode, body: String) -> Self {
+        #[derive(serde::Deserialize)]
+        struct Body {
+            code: u32,
+            message: Option<String>
+        }
+        match serde_json::from_str::<Body>(body.as_str()) {
+            Ok(parsed) => ServerError {
+                status,
+                code: Some(parsed.code),
+                message: parsed.message.unwrap_or(body)
+            },
+            Err(_) => ServerError { status, code: None, message: body }
+        }
+    }
+}
+
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "status: {}, code: {}, message: {}", self.status, code, self.message),
+            None => write!(f, "status: {}, message: {}", self.status, self.message)
+        }
+    }
+}
+
+/// misconfiguration found when a config is built or a client is created
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("server list is empty")]
+    EmptyServerList,
+    #[error("invalid server: {0}")]
+    InvalidServer(String),
+    #[error("invalid value of {0}: {1}")]
+    InvalidValue(String, String),
+    #[error("can't obtain home dir, please set the cache dir")]
+    NoCacheDir,
+    #[error("invalid nacos url: {0}")]
+    InvalidUrl(String),
+    #[error("unsupported config file: {0}")]
+    UnsupportedFormat(String),
+    #[error("failed to parse config: {0}")]
+    Parse(String),
+    #[error("{0}: {1}")]
+    Io(String, std::io::Error)
 }
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error(transparent)]
     Core(#[from] nacos_sdk_core::Error),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
     #[error("{0}: {1}")]
     Fs(String, std::io::Error),
+    #[error("{0}: {1}")]
+    Io(String, std::io::Error),
     #[error("no host to srv serviceInfo: {0}")]
     NoHostToService(String),
     #[error(transparent)]
@@ -24,15 +143,129 @@ pub enum Error {
     #[error(transparent)]
     Serde(#[from] serde_json::Error),
 
-    #[error("nacos server error; status: {0}, message: {1}")]
-    NacosRemote(StatusCode, String),
+    #[error("nacos server error; {0}")]
+    NacosRemote(ServerError),
+    /// 401 or 403: wrong credentials, expired token or missing permission
+    #[error("nacos auth failed; {0}")]
+    Auth(ServerError),
+    #[error("not found; {0}")]
+    NotFound(ServerError),
+    /// 429: the server limits the request rate of this client
+    #[error("throttled by nacos server; {0}")]
+    Throttled(ServerError),
+    /// 502, 503 or 504: the server is overloaded, starting or behind a broken proxy
+    #[error("nacos server busy; {0}")]
+    ServerBusy(ServerError),
+    /// every server of the list failed, keyed by server address; if none of them may be retried
+    /// the error of the last server is returned instead
+    #[error("all nacos servers failed: {}", .0.iter().map(|(server, error)| format!("{}: {}", server, error)).join("; "))]
+    AllServersFailed(Vec<(String, Error)>),
 
     #[error("found invalid header value")]
     InvalidHeaderValue(#[from] reqwest::header::InvalidHeaderValue),
+    /// failed instances of a batch operation, keyed by `ip:port`
+    #[error("{} instances failed: {}", .0.len(), .0.iter().map(|(key, error)| format!("{}: {}", key, error)).join("; "))]
+    Batch(Vec<(String, Error)>),
+    #[cfg(feature = "prometheus")]
+    #[error(transparent)]
+    Metrics(#[from] prometheus::Error),
+    #[error("invalid selector: {0}")]
+    InvalidSelector(String),
+    #[error("naming client is closed")]
+    Closed,
+    #[error("timed out: {0}")]
+    Timeout(String),
     #[error("{0}")]
     Custom(String),
     #[error("unknown error occurred")]
     Unknown
 }
 
-pub type Result<T> = std::result::Result<T, Error>;
\ No newline at end of file
+impl Error {
+    /// classify a non 200 response
+    pub(crate) fn from_response(status: StatusCode, body: String) -> Self {
+        let error = ServerError::new(status, body);
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Error::Auth(error),
+            StatusCode::NOT_FOUND => Error::NotFound(error),
+            StatusCode::TOO_MANY_REQUESTS => Error::Throttled(error),
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT => Error::ServerBusy(error),
+            _ => Error::NacosRemote(error)
+        }
+    }
+
+    /// whether the same call may succeed later or on another server;
+    /// bad requests, auth failures and misconfiguration are not retryable
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Net(error) => !(error.is_builder() || error.is_decode() || error.is_body()),
+            Error::Io(..) | Error::Throttled(_) | Error::ServerBusy(_) | Error::Timeout(_) => true,
+            Error::NacosRemote(error) => match error.code.and_then(|code| RespCode::try_from(code).ok()) {
+                Some(code) => code.is_retryable(),
+                None => error.status.is_server_error()
+            },
+            Error::AllServersFailed(errors) | Error::Batch(errors) => {
+                errors.iter().any(|(_, error)| error.is_retryable())
+            },
+            _ => false
+        }
+    }
+
+    /// the business code sent by the server, if any
+    pub fn server_code(&self) -> Option<RespCode> {
+        self.server_error().and_then(|error| error.code).and_then(|code| RespCode::try_from(code).ok())
+    }
+
+    /// the failed response, for [Error::AllServersFailed] the one of the last server
+    pub fn server_error(&self) -> Option<&ServerError> {
+        match self {
+            Error::NacosRemote(error) | Error::Auth(error) | Error::NotFound(error)
+                | Error::Throttled(error) | Error::ServerBusy(error) => Some(error),
+            Error::AllServersFailed(errors) => errors.last().and_then(|(_, error)| error.server_error()),
+            _ => None
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// collect the results of a batch into [Error::Batch] if any instance failed
+pub(crate) fn batch_result(results: Vec<(String, Result<()>)>) -> Result<()> {
+    let failed = results.into_iter()
+        .filter_map(|(key, res)| res.err().map(|error| (key, error)))
+        .collect::<Vec<_>>();
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Batch(failed))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::StatusCode;
+
+    use super::{Error, RespCode};
+
+    #[test]
+    fn test_classify() {
+        let error = Error::from_response(StatusCode::FORBIDDEN, "unknown user!".to_owned());
+        assert!(matches!(error, Error::Auth(_)) && !error.is_retryable());
+
+        let error = Error::from_response(
+            StatusCode::INTERNAL_SERVER_ERROR, r#"{"code":21003,"message":"instance not found","data":null}"#.to_owned()
+        );
+        assert_eq!(error.server_code(), Some(RespCode::InstanceNotFound));
+        assert_eq!(RespCode::try_from(10200), Ok(RespCode::Ok));
+        assert_eq!(RespCode::try_from(12345), Err(12345));
+        assert!(!error.is_retryable());
+        assert!(Error::from_response(StatusCode::INTERNAL_SERVER_ERROR, "caused: oops".to_owned()).is_retryable());
+
+        let error = Error::AllServersFailed(vec![
+            ("127.0.0.1:8848".to_owned(), Error::from_response(StatusCode::BAD_REQUEST, "bad".to_owned())),
+            ("127.0.0.2:8848".to_owned(), Error::from_response(StatusCode::SERVICE_UNAVAILABLE, "busy".to_owned()))
+        ]);
+        assert!(error.is_retryable());
+        assert_eq!(error.server_error().unwrap().status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
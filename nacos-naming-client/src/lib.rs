@@ -4,12 +4,22 @@ pub mod error;
 mod util;
 mod client;
 mod config;
+mod syncer;
+mod selector;
+mod trace;
+mod monitor;
 pub mod constants;
 pub use data::model;
 pub use config::*;
 pub use client::*;
-pub use data::ServiceChangeListener;
-pub use net::{NamingRemote, HttpNamingRemote};
+pub use data::{ServiceChangeListener, ServiceUpdater};
+pub use net::{NamingRemote, TokenSource, HttpNamingRemote, PushStats};
+#[cfg(any(test, feature = "testing"))]
+pub use net::{InMemoryNamingRemote, RemoteCall};
+pub use syncer::{InstanceSyncer, SyncReport};
+pub use selector::{Selector, Matcher};
+#[cfg(feature = "tracing")]
+pub use trace::{TraceParent, TRACEPARENT_HEADER, with_traceparent};
 
 #[cfg(test)]
 mod test {
@@ -17,9 +27,11 @@ mod test {
     use std::time::Duration;
 
     use async_trait::async_trait;
+    use nacos_mock_server::{MockServer, MockInstance};
+    use tokio::sync::mpsc;
 
     use crate::{
-        NamingClient, config::{NamingConfig, ServerConfig}, constants, 
+        NamingClient, ShutdownOptions, config::{NamingConfig, PushConfig},
         error::Result, model::Instance, ServiceChangeListener
     };
 
@@ -34,41 +46,84 @@ mod test {
     }
 
     #[tokio::test]
-    
     async fn test_beat() -> Result<()> {
         init_logger();
-        let config = NamingConfig {
-            namespace_id: "public".to_string(),
-            cluster: constants::DEFAULT_CLUSTER.to_owned(),
-            group: constants::DEFAULT_GROUP.to_owned(),
-            server_list: vec![ServerConfig::new(
-                "http".to_string(), "192.168.1.221:8848".to_string(), "nacos".to_string()
-            )],
-            cache_dir: "/workspaces/nacos-sdk-rust/output/failover".to_owned(),
-            load_at_start: false,
-            update_when_empty: false,
-            user_name: Some("nacos".to_string()),
-            password: Some("nacos".to_string()),
-        };
-        let client = NamingClient::new_http(config).await;
-        
-        // client.register_instance(Instance::new_with_defaults("test", "192.168.1.221", 8888)).await?;
-        client.subscribe("service-im", "DEFAULT_GROUP", vec!["DEFAULT"], Listener).await?;
-        //let instances = client.select_instances("c4", "DEFAULT_GROUP", vec!["DEFAULT"], false).await?;
-        //println!("server data => \n{:?}", instances);
-        tokio::time::sleep(Duration::from_secs(60 * 10)).await;
+        let server = MockServer::builder()
+            .auth("nacos", "nacos")
+            .beat_interval(Duration::from_millis(2100))
+            .start().await
+            .unwrap();
+        let cache_dir = std::env::temp_dir().join(format!("nacos-client-beat-{}", std::process::id()));
+        let config = NamingConfig::builder()
+            .server_addr(server.url().as_str())
+            .auth("nacos", "nacos")
+            .cache_dir(cache_dir.to_str().unwrap())
+            .push(PushConfig { client_ip: Some("127.0.0.1".to_owned()), ..PushConfig::default() })
+            .build()?;
+        let client = NamingClient::new_http(config).await?;
+        assert_eq!(server.stats().logins, 1);
 
+        client.register_instance(Instance::new_with_defaults("test", "127.0.0.1", 8888)).await?;
+        wait_until(|| server.stats().beats >= 2).await;
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        client.subscribe("test", "DEFAULT_GROUP", vec!["DEFAULT"], Listener(tx)).await?;
+        // the subscriber is known to the server after its first query
+        wait_until(|| server.subscribers("public", "DEFAULT_GROUP", "test") > 0).await;
+        server.register_instance("public", "DEFAULT_GROUP", "test", MockInstance::new("127.0.0.2", 8888)).await;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while rx.recv().await.map(|hosts| hosts.len()) != Some(2) {}
+        }).await.expect("no push received");
+        // the push is acked after the listeners are notified
+        wait_until(|| server.stats().push_acks >= 1).await;
+
+        client.shutdown(ShutdownOptions::default()).await?;
+        let instances = server.instances("public", "DEFAULT_GROUP", "test");
+        assert!(instances.len() == 1 && instances[0].ip == "127.0.0.2");
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(cache_dir);
         Ok(())
     }
 
-    struct Listener;
+    #[tokio::test]
+    async fn test_token_refresh() -> Result<()> {
+        init_logger();
+        let server = MockServer::builder().auth("nacos", "nacos").token_ttl(2).start().await.unwrap();
+        let cache_dir = std::env::temp_dir().join(format!("nacos-client-token-{}", std::process::id()));
+        let config = NamingConfig::builder()
+            .server_addr(server.url().as_str())
+            .auth("nacos", "nacos")
+            .cache_dir(cache_dir.to_str().unwrap())
+            .push(PushConfig::disabled())
+            .build()?;
+        let client = NamingClient::new_http(config).await?;
+        // the token is refreshed every ttl/2, the third login is made after the first token expired
+        wait_until(|| server.stats().logins >= 3).await;
+        client.register_instance(Instance::new_with_defaults("test", "127.0.0.1", 8888)).await?;
+        assert_eq!(server.instances("public", "DEFAULT_GROUP", "test").len(), 1);
+
+        client.shutdown(ShutdownOptions::default()).await?;
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(cache_dir);
+        Ok(())
+    }
+
+    async fn wait_until<F: Fn() -> bool>(condition: F) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        }).await.expect("condition not met in time");
+    }
+
+    struct Listener(mpsc::UnboundedSender<Vec<Instance>>);
 
     #[async_trait]
     impl ServiceChangeListener for Listener {
         async fn changed(&self, service_name: &str, hosts: Vec<Instance>) {
-            println!("changed => {}:{:?}", service_name, hosts);
+            log::info!("changed => {}:{:?}", service_name, hosts);
+            let _ = self.0.send(hosts);
         }
     }
 
 }
-
@@ -1,39 +1,143 @@
-use prometheus::{
-    IntGaugeVec, HistogramVec, Histogram, HistogramTimer, 
-    core::{GenericGauge, AtomicI64}
-};
+//! prometheus metrics of a naming client, registered into the registry of [crate::NamingConfig];
+//! names and labels follow the java client, plus the const labels `namespace` and `client` so that
+//! several clients can share a registry. Without the `prometheus` feature every call is a no-op.
+//! udp push packets are counted here once the push receiver is attached to a client, and by [crate::PushStats]
+use std::time::Duration;
 
-use lazy_static::lazy_static;
+#[cfg(feature = "prometheus")]
+use std::{collections::HashMap, sync::{Arc, atomic::{AtomicUsize, Ordering}}};
+
+#[cfg(feature = "prometheus")]
 use prometheus::{
-    register_int_gauge_vec, register_histogram_vec
+    IntGaugeVec, HistogramVec, IntCounter, IntCounterVec, Opts, HistogramOpts, Registry
 };
 
-lazy_static! {
-    static ref NACOS_MONITOR: IntGaugeVec = register_int_gauge_vec!(
-        "nacos_monitor", "nacos_monitor", &["module", "name"]).unwrap();
-    static ref NACOS_CLIENT_REQUEST_HISTOGRAM: HistogramVec = register_histogram_vec!(
-        "nacos_client_request", 
-        "nacos_client_request", 
-        &["module", "method", "url", "code"]
-    ).unwrap();
-}
+/// the `client` label, numbered in order of creation within the process
+#[cfg(feature = "prometheus")]
+static CLIENT_ID: AtomicUsize = AtomicUsize::new(0);
 
-pub fn service_info_map_size_monitor() -> GenericGauge<AtomicI64> {
-    NACOS_MONITOR.with_label_values(&["naming", "serviceInfoMapSize"])
+#[cfg(feature = "prometheus")]
+struct Collectors {
+    monitor: IntGaugeVec,
+    request: HistogramVec,
+    token_failures: IntCounter,
+    push_packets: IntCounterVec
 }
 
-pub fn dom2beat_size_monitor() -> GenericGauge<AtomicI64> {
-    NACOS_MONITOR.with_label_values(&["naming", "dom2BeatSize"])
+/// handle to the metrics of one client; disabled if no registry is configured
+#[derive(Clone, Default)]
+pub struct Monitor {
+    #[cfg(feature = "prometheus")]
+    collectors: Option<Arc<Collectors>>
 }
 
-pub fn listen_config_count_monitor() -> GenericGauge<AtomicI64> {
-    NACOS_MONITOR.with_label_values(&["naming", "listenConfigCount"])
-}
+impl Monitor {
+    #[cfg(feature = "prometheus")]
+    pub fn new(registry: &Registry, namespace_id: &str) -> prometheus::Result<Self> {
+        let labels = HashMap::from([
+            ("namespace".to_owned(), namespace_id.to_owned()),
+            ("client".to_owned(), CLIENT_ID.fetch_add(1, Ordering::Relaxed).to_string())
+        ]);
+        let monitor = IntGaugeVec::new(
+            Opts::new("nacos_monitor", "nacos_monitor").const_labels(labels.clone()), &["module", "name"]
+        )?;
+        let request = HistogramVec::new(
+            HistogramOpts::new("nacos_client_request", "nacos_client_request").const_labels(labels.clone()),
+            &["module", "method", "url", "code"]
+        )?;
+        let token_failures = IntCounter::with_opts(
+            Opts::new("nacos_client_token_refresh_failures", "failed logins of the token refresh task")
+                .const_labels(labels.clone())
+        )?;
+        let push_packets = IntCounterVec::new(
+            Opts::new("nacos_client_push_packets", "udp push packets by result: received, acked or rejected")
+                .const_labels(labels),
+            &["result"]
+        )?;
+        registry.register(Box::new(monitor.clone()))?;
+        registry.register(Box::new(request.clone()))?;
+        registry.register(Box::new(token_failures.clone()))?;
+        registry.register(Box::new(push_packets.clone()))?;
+        Ok(Monitor {
+            collectors: Some(Arc::new(Collectors { monitor, request, token_failures, push_packets }))
+        })
+    }
+
+    /// latency of one http attempt; `code` is the http status, or `error` if there was no response
+    pub fn observe_request(&self, method: &str, path: &str, code: &str, latency: Duration) {
+        #[cfg(feature = "prometheus")]
+        if let Some(collectors) = self.collectors.as_ref() {
+            collectors.request.with_label_values(&["naming", method, path, code]).observe(latency.as_secs_f64());
+        }
+        #[cfg(not(feature = "prometheus"))]
+        let _ = (method, path, code, latency);
+    }
+
+    pub fn service_info_map_size(&self, size: usize) {
+        self.set_gauge("serviceInfoMapSize", size)
+    }
+
+    /// instances beating, the java client's `dom2BeatSize`
+    pub fn beat_size(&self, size: usize) {
+        self.set_gauge("dom2BeatSize", size)
+    }
 
-pub fn config_request_monitor(method: &str, url: &str, code: &str) -> HistogramTimer {
-    NACOS_CLIENT_REQUEST_HISTOGRAM.with_label_values(&["config", method, url, code]).start_timer()
+    pub fn token_refresh_failed(&self) {
+        #[cfg(feature = "prometheus")]
+        if let Some(collectors) = self.collectors.as_ref() {
+            collectors.token_failures.inc();
+        }
+    }
+
+    /// one udp push packet; `result` is `received`, `acked` or `rejected`
+    pub fn push_packet(&self, result: &str) {
+        #[cfg(feature = "prometheus")]
+        if let Some(collectors) = self.collectors.as_ref() {
+            collectors.push_packets.with_label_values(&[result]).inc();
+        }
+        #[cfg(not(feature = "prometheus"))]
+        let _ = result;
+    }
+
+    fn set_gauge(&self, name: &str, value: usize) {
+        #[cfg(feature = "prometheus")]
+        if let Some(collectors) = self.collectors.as_ref() {
+            collectors.monitor.with_label_values(&["naming", name]).set(value as i64);
+        }
+        #[cfg(not(feature = "prometheus"))]
+        let _ = (name, value);
+    }
 }
 
-pub fn naming_request_monitor(method: &str, url: &str, code: &str) -> Histogram {
-    NACOS_CLIENT_REQUEST_HISTOGRAM.with_label_values(&["config", method, url, code])
-}
\ No newline at end of file
+#[cfg(all(test, feature = "prometheus"))]
+mod test {
+    use std::time::Duration;
+
+    use prometheus::Registry;
+
+    use super::Monitor;
+
+    #[test]
+    fn test_monitor() {
+        let registry = Registry::new();
+        let monitor = Monitor::new(&registry, "public").unwrap();
+        monitor.observe_request("GET", "/nacos/v1/ns/instance/list", "200", Duration::from_millis(5));
+        monitor.beat_size(2);
+        monitor.push_packet("received");
+        monitor.push_packet("acked");
+        // another client shares the registry, told apart by the client label
+        Monitor::new(&registry, "public").unwrap().beat_size(3);
+
+        let families = registry.gather();
+        let find = |name: &str| families.iter().find(|family| family.get_name() == name).unwrap();
+        let request = &find("nacos_client_request").get_metric()[0];
+        assert!(request.get_label().iter().any(|label| label.get_name() == "module" && label.get_value() == "naming"));
+        assert!(request.get_label().iter().any(|label| label.get_name() == "namespace" && label.get_value() == "public"));
+        let mut sizes = find("nacos_monitor").get_metric().iter()
+            .map(|metric| metric.get_gauge().get_value())
+            .collect::<Vec<_>>();
+        sizes.sort_by(f64::total_cmp);
+        assert_eq!(sizes, vec![2f64, 3f64]);
+        assert_eq!(find("nacos_client_push_packets").get_metric().len(), 2);
+    }
+}
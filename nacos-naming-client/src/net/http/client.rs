@@ -1,17 +1,18 @@
-use std::{time::Duration, any::Any};
+use std::{time::{Duration, Instant}, any::Any, sync::{Arc, RwLock}};
 
 use reqwest::{header::{HeaderMap, HeaderValue}, StatusCode, Method};
 use serde::{Serialize, de::DeserializeOwned};
 
-use crate::error::*;
+use crate::{error::*, monitor::Monitor, trace};
 
 #[derive(Clone)]
 pub struct HttpClient {
     inner: reqwest::Client,
+    monitor: Arc<RwLock<Monitor>>
 }
 
 impl HttpClient {
-    pub fn new() -> HttpClient {
+    pub fn new(monitor: Monitor) -> HttpClient {
         let http_client = reqwest::ClientBuilder::new()
             .connect_timeout(Duration::from_secs(6))
             .tcp_keepalive(Some(Duration::from_secs(10)))
@@ -21,11 +22,17 @@ impl HttpClient {
             .build()
             .expect("failed to build http client");
         
-            HttpClient {
-            inner: http_client
+        HttpClient {
+            inner: http_client,
+            monitor: Arc::new(RwLock::new(monitor))
         }
     }
 
+    /// record the requests into the metrics of the client the remote is attached to
+    pub fn set_monitor(&self, monitor: Monitor) {
+        *self.monitor.write().expect("[http] lock poisoned") = monitor;
+    }
+
     fn default_headers() -> HeaderMap {
         let mut headers = HeaderMap::new();
         let client_version = "Nacos-Go-Client:v".to_string() + std::env!("CARGO_PKG_VERSION");
@@ -49,33 +56,65 @@ impl HttpClient {
     pub async fn request_str<Req: Serialize + ?Sized>(
         &self, base: &[String], path: &str, method: reqwest::Method, data: &Req
     ) -> Result<String> {
+        if base.is_empty() {
+            return Err(ConfigError::EmptyServerList.into());
+        }
         let mut index = rand::random::<usize>() % base.len();
 
-        for (i, _) in base.iter().enumerate() {
+        let mut errors = vec![];
+        for _ in base.iter() {
             let url = format!("{}{}", &base[index], path);
+            let start = Instant::now();
             let res = self.send_request(url.as_str(), method.clone(), data).await;
+            let code = match &res {
+                Ok(_) => StatusCode::OK.as_str().to_owned(),
+                Err(error) => error.server_error().map(|error| error.status.as_str().to_owned()).unwrap_or("error".to_owned())
+            };
+            self.monitor.read().expect("[http] lock poisoned")
+                .observe_request(method.as_str(), path, code.as_str(), start.elapsed());
+            match &res {
+                Ok(_) => trace::record_response(&base[index], Some(StatusCode::OK.as_u16()), None, start.elapsed()),
+                Err(error) => trace::record_response(
+                    &base[index], error.server_error().map(|error| error.status.as_u16()), Some(error), start.elapsed()
+                )
+            }
             match res {
                 Ok(resp) => return Ok(resp),
+                // a nacos business code rejects the request itself, other servers would answer the same;
+                // auth failures and other 4xx may come from one misconfigured server or proxy, so keep rotating
+                Err(error) if error.server_code().is_some_and(|code| !code.is_retryable()) => return Err(error),
                 Err(error) => {
                     log::error!("call nacos server[{}] error: {}", url, error);
+                    errors.push((base[index].clone(), error));
                 }
             }
-            index = (index + i) % base.len()
+            index = (index + 1) % base.len()
         }
-        Err(Error::Custom(format!("retry {} times http request failed", base.len())))
+        // every server refused the request itself, e.g. an auth failure: its own error tells the caller more
+        if errors.iter().all(|(_, error)| !error.is_retryable()) {
+            if let Some((_, error)) = errors.pop() {
+                return Err(error);
+            }
+        }
+        Err(Error::AllServersFailed(errors))
     }
 
     async fn send_request<Req: Serialize + ?Sized>(
         &self, url: &str, method: reqwest::Method, data: &Req
     ) -> Result<String> {
         log::trace!("send http request: {}", url);
+        let request_id = uuid::Uuid::new_v4().to_string();
         let request = self.inner.request(method.clone(), url);
-        let result = match method {
+        let request = match method {
             Method::GET => request.query(data),
             _ => request.form(data)
-        }
-        .header("RequestId", uuid::Uuid::new_v4().to_string())
-        .send().await?;
+        };
+        #[cfg(feature = "tracing")]
+        let request = match trace::traceparent(request_id.as_str()) {
+            Some(traceparent) => request.header(trace::TRACEPARENT_HEADER, traceparent),
+            None => request
+        };
+        let result = request.header("RequestId", request_id).send().await?;
 
         
         match result.status() {
@@ -84,7 +123,7 @@ impl HttpClient {
                 log::debug!("[request_nacos]path: {} resp: {:?}", url, resp_text);
                 Ok(resp_text)
             },
-            code@_ => Err(Error::NacosRemote(code, result.text().await?))
+            code => Err(Error::from_response(code, result.text().await?))
         }
     }
 }
\ No newline at end of file
@@ -1,5 +1,11 @@
 mod client;
 mod remote;
 mod push_receiver;
+#[cfg(test)]
+mod test;
 
 pub use remote::HttpNamingRemote;
+
+/// the client cache pushes and polls are written to; empty until a client attaches
+type UpdaterSlot = std::sync::Arc<std::sync::RwLock<Option<crate::data::ServiceUpdater>>>;
+pub use push_receiver::PushStats;
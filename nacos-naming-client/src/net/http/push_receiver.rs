@@ -1,15 +1,36 @@
-use std::{io::Read, net::SocketAddr};
+use std::{
+    collections::HashSet,
+    io::Read,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, RwLock, atomic::{AtomicU64, Ordering}},
+    time::{Duration, SystemTime, UNIX_EPOCH}
+};
 
 use serde::{Deserialize, Serialize};
 use tokio::{net::UdpSocket, sync::mpsc};
 use flate2::read::GzDecoder;
 
-use crate::data::ServiceHolder;
+use crate::{
+    config::PushConfig,
+    constants,
+    data::{ServiceHolder, model::ServiceInfo},
+    error::{Error, Result},
+    monitor::Monitor,
+    util::TaskGroup,
+    trace
+};
 
+use super::UpdaterSlot;
 
-pub const PUSH_TYPE_DOM: &'static str = "dom";
-pub const PUSH_TYPE_SERVICE: &'static str = "service";
-pub const PUSH_TYPE_DUMP: &'static str = "dump";
+/// unknown sources trigger a new dns lookup of the server list at most once per interval
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(30);
+/// pushes whose `lastRefTime` is further ahead of the local clock are forged or from a broken server clock
+const MAX_REF_TIME_AHEAD: Duration = Duration::from_secs(10 * 60);
+
+
+pub const PUSH_TYPE_DOM: &str = "dom";
+pub const PUSH_TYPE_SERVICE: &str = "service";
+pub const PUSH_TYPE_DUMP: &str = "dump";
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")] 
@@ -20,67 +41,194 @@ pub struct PushPacket {
     data: String
 }
 
+/// counters of udp push packets
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PushStats {
+    pub received: u64,
+    pub acked: u64,
+    /// packets from unknown sources, malformed packets and stale pushes
+    pub rejected: u64
+}
+
+/// backs [PushStats], and the push metrics of the client the receiver is attached to
+#[derive(Default)]
+struct PushCounter {
+    received: AtomicU64,
+    acked: AtomicU64,
+    rejected: AtomicU64,
+    monitor: RwLock<Monitor>
+}
+
+impl PushCounter {
+    fn received(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.monitor().push_packet("received");
+    }
+
+    fn acked(&self) {
+        self.acked.fetch_add(1, Ordering::Relaxed);
+        self.monitor().push_packet("acked");
+    }
+
+    fn rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        self.monitor().push_packet("rejected");
+    }
+
+    fn monitor(&self) -> std::sync::RwLockReadGuard<'_, Monitor> {
+        self.monitor.read().expect("[push_receiver] lock poisoned")
+    }
+}
+
+/// drops packets from unknown sources and pushes from the future; pushes of verified sources must also be
+/// newer than the cached info, which a poll of the server resets
+struct PushGuard {
+    verify_source: bool,
+    /// addresses of the server list, refreshed by the [SourceResolver]
+    allowed: Arc<RwLock<HashSet<IpAddr>>>,
+    /// asks the resolver for a new lookup, none if the source isn't verified
+    resolve: Option<mpsc::Sender<()>>
+}
+
+/// looks up the server list off the receive loop, so a slow dns never delays pushes
+struct SourceResolver {
+    servers: Vec<(String, u16)>,
+    allowed: Arc<RwLock<HashSet<IpAddr>>>
+}
+
 pub struct PushReceiver {
     udp_port: u16,
-    shutdown: mpsc::Sender<()>
+    counter: Arc<PushCounter>
 }
 
 impl PushReceiver {
-    pub async fn new(udp_port: u16, service_holder: ServiceHolder) -> Self {
-        let (tx, rx) = mpsc::channel(1);
+    /// the receive task is spawned into `tasks`, the socket is closed when it has ended
+    pub(crate) async fn new(
+        config: &PushConfig, servers: &[String], updater: UpdaterSlot, tasks: &TaskGroup
+    ) -> Result<Self> {
+        let sock = Self::bind(config).await?;
+        let udp_port = sock.local_addr()
+            .map_err(|err| Error::Io("can not obtain udp socket address".to_owned(), err))?
+            .port();
+        let guard = match config.verify_source {
+            true => {
+                let resolver = SourceResolver::new(servers)?;
+                resolver.resolve().await;
+                let (tx, rx) = mpsc::channel(1);
+                let guard = PushGuard::new(resolver.allowed.clone(), Some(tx));
+                tasks.spawn(trace::background(resolver.run(rx), "push_resolver", servers.join(",").as_str()));
+                guard
+            },
+            false => PushGuard::new(Arc::default(), None)
+        };
         let receiver = Self {
             udp_port,
-            shutdown: tx
+            counter: Arc::new(PushCounter::default())
         };
 
-        let sock = UdpSocket::bind(("0.0.0.0", receiver.udp_port))
-        .await
-        .expect("failed to open receiver udp socket");
+        let task = Self::run(updater, sock, guard, receiver.counter.clone());
+        tasks.spawn(trace::background(task, "push_receiver", udp_port.to_string().as_str()));
+        Ok(receiver)
+    }
+
+    pub fn udp_port(&self) -> u16 {
+        self.udp_port
+    }
 
-        tokio::spawn(Self::run(service_holder, sock, rx));
-        receiver
+    /// count the packets into the metrics of the client the remote is attached to
+    pub(crate) fn set_monitor(&self, monitor: Monitor) {
+        *self.counter.monitor.write().expect("[push_receiver] lock poisoned") = monitor;
+    }
+
+    pub fn stats(&self) -> PushStats {
+        PushStats {
+            received: self.counter.received.load(Ordering::Relaxed),
+            acked: self.counter.acked.load(Ordering::Relaxed),
+            rejected: self.counter.rejected.load(Ordering::Relaxed)
+        }
     }
 
-    pub async fn shutdown(&self) {
-        match self.shutdown.send(()).await {
-            Err(_) => log::warn!("failed to send shutdown signal to receiver"),
-            _ => {}
+    /// bind the configured port, or try random ports in the push port range until one is free
+    async fn bind(config: &PushConfig) -> Result<UdpSocket> {
+        let bind_ip = config.bind_ip.as_str();
+        if let Some(port) = config.port {
+            return UdpSocket::bind((bind_ip, port)).await
+                .map_err(|err| Error::Io(format!("failed to bind udp socket {}:{}", bind_ip, port), err));
+        }
+
+        let range = constants::PUSH_PORT_END - constants::PUSH_PORT_START;
+        let mut last_error = None;
+        for _ in 0..config.bind_retries.max(1) {
+            let port = rand::random::<u16>() % range + constants::PUSH_PORT_START;
+            match UdpSocket::bind((bind_ip, port)).await {
+                Ok(sock) => return Ok(sock),
+                Err(err) => {
+                    log::warn!("failed to bind udp socket {}:{}, try another port: {}", bind_ip, port, err);
+                    last_error = Some(err);
+                }
+            }
         }
+        let err = last_error.expect("[push_receiver] bind retries at least once");
+        Err(Error::Io(
+            format!("failed to bind udp socket on {} after {} retries", bind_ip, config.bind_retries.max(1)), err
+        ))
     }
 
-    async fn run(holder: ServiceHolder, sock: UdpSocket, mut signal: mpsc::Receiver<()>) {
+    async fn run(
+        updater: UpdaterSlot,
+        sock: UdpSocket,
+        guard: PushGuard,
+        counter: Arc<PushCounter>
+    ) {
         let mut buf = [0; 65536];
         loop {
-            let res = Self::read_from_socket(&mut buf, &sock, &mut signal).await;
+            let res = Self::read_from_socket(&mut buf, &sock).await;
             let (len, socket_addr) = match res {
                 None => continue,
                 Some(x) => x
             };
+            counter.received();
+
+            if !guard.accept_source(socket_addr.ip().to_canonical()) {
+                log::warn!("reject push message from unknown source: {}", socket_addr);
+                counter.rejected();
+                continue
+            }
 
             let packet = match Self::parse_packet(&buf, len) {
                 Some(x) => x,
-                None => continue
+                None => {
+                    counter.rejected();
+                    continue
+                }
             };
 
-            let reply = Self::build_reply(packet, &holder).await;
+            let holder = updater.read().expect("[push_receiver] lock poisoned").clone();
+            let holder = match holder {
+                Some(updater) => updater.holder().clone(),
+                None => {
+                    // not acked, the server pushes again after a client is attached
+                    log::debug!("push message ignored, no client attached: {}", socket_addr);
+                    counter.rejected();
+                    continue
+                }
+            };
+            let reply = Self::build_reply(packet, &holder, &guard, &counter).await;
             match sock.send_to(&reply[..], socket_addr).await {
                 Err(error) => log::error!("push channel failed: {}", error),
-                Ok(len) => log::debug!("push ack success; len: {}", len)
+                Ok(len) => {
+                    counter.acked();
+                    log::debug!("push ack success; len: {}", len)
+                }
             }
         }
     } 
 
     async fn read_from_socket(
         buf: &mut [u8], 
-        sock: &UdpSocket, 
-        signal: &mut mpsc::Receiver<()>
+        sock: &UdpSocket
     ) -> Option<(usize, SocketAddr)> {
-        let res = tokio::select!{
-            res = sock.recv_from(buf) => res,
-            _ = signal.recv() => return None
-        };
-        
-        match res {
+        match sock.recv_from(buf).await {
             Err(err) => {
                 log::warn!("receive illegal push message: {}", err);
                 None
@@ -131,7 +279,12 @@ impl PushReceiver {
         }
     }
 
-    async fn build_reply(packet: PushPacket, holder: &ServiceHolder) -> Vec<u8> {
+    async fn build_reply(
+        packet: PushPacket,
+        holder: &ServiceHolder,
+        guard: &PushGuard,
+        counter: &PushCounter
+    ) -> Vec<u8> {
         let mut default = PushPacket {
             push_type: "unknown-ack".to_string(), 
             last_ref_time: packet.last_ref_time, 
@@ -139,15 +292,27 @@ impl PushReceiver {
         };
         let reply = match packet.push_type.as_str() {
             PUSH_TYPE_DOM | PUSH_TYPE_SERVICE => {
-                let service_info = serde_json::from_str(packet.data.as_str());
+                let service_info = serde_json::from_str::<ServiceInfo>(packet.data.as_str());
                 match service_info {
-                    Ok(info) => holder.update_service_info(info).await,
-                    Err(error) => log::error!("can not serialize push data: {}\n{}", error, packet.data)
+                    Ok(info) => {
+                        // rejected pushes are acked too, to stop the server from resending;
+                        // the ordering of unverified pushes is not trusted, a forged one could block the real ones
+                        if !guard.accept_ref_time(info.last_ref_time)
+                            || !holder.update_pushed_info(info, guard.verify_source).await {
+                            log::warn!("push message ignored, last_ref_time: {}", packet.last_ref_time);
+                            counter.rejected();
+                        }
+                    },
+                    Err(error) => {
+                        log::error!("can not serialize push data: {}\n{}", error, packet.data);
+                        counter.rejected();
+                    }
                 }
                 default.push_type = "push-ack".to_string();
                 default
             },
-            PUSH_TYPE_DUMP => {
+            // the dump holds every cached service, only known servers may ask for it
+            PUSH_TYPE_DUMP if guard.verify_source => {
                 let data = serde_json::to_string(&holder.get_service_info_map().await)
                 .expect("failed to serialize service_holder map");
                 default.data = data;
@@ -160,3 +325,159 @@ impl PushReceiver {
         serde_json::to_vec(&reply).expect("failed to serialize push ack")
     }
 }
+
+impl PushGuard {
+    fn new(allowed: Arc<RwLock<HashSet<IpAddr>>>, resolve: Option<mpsc::Sender<()>>) -> Self {
+        PushGuard { verify_source: resolve.is_some(), allowed, resolve }
+    }
+
+    /// an unknown source is rejected and triggers a new lookup; the server resends the push if it was right
+    fn accept_source(&self, ip: IpAddr) -> bool {
+        let resolve = match self.resolve.as_ref() {
+            None => return true,
+            Some(resolve) => resolve
+        };
+        if self.allowed.read().expect("[push_receiver] lock poisoned").contains(&ip) {
+            return true;
+        }
+        // a lookup is already queued if full
+        let _ = resolve.try_send(());
+        false
+    }
+
+    /// a push far ahead of now would be newer than every later push of the server
+    fn accept_ref_time(&self, last_ref_time: u64) -> bool {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        u128::from(last_ref_time) <= (now + MAX_REF_TIME_AHEAD).as_millis()
+    }
+}
+
+impl SourceResolver {
+    fn new(servers: &[String]) -> Result<Self> {
+        let mut hosts = Vec::with_capacity(servers.len());
+        for server in servers {
+            let url = reqwest::Url::parse(server)
+                .map_err(|err| Error::Custom(format!("invalid server address[{}]: {}", server, err)))?;
+            let host = url.host_str()
+                .ok_or_else(|| Error::Custom(format!("server address has no host: {}", server)))?;
+            let port = url.port_or_known_default().unwrap_or_default();
+            hosts.push((host.trim_matches(|c| c == '[' || c == ']').to_owned(), port));
+        }
+        Ok(SourceResolver { servers: hosts, allowed: Arc::default() })
+    }
+
+    async fn resolve(&self) {
+        let mut resolved = HashSet::new();
+        for (host, port) in self.servers.iter() {
+            match tokio::net::lookup_host((host.as_str(), *port)).await {
+                Ok(addrs) => resolved.extend(addrs.map(|addr| addr.ip())),
+                Err(err) => log::warn!("failed to resolve nacos server[{}]: {}", host, err)
+            }
+        }
+        log::debug!("accept push message from: {:?}", resolved);
+        // addresses are only added, a failed lookup must not reject the servers known so far
+        self.allowed.write().expect("[push_receiver] lock poisoned").extend(resolved);
+    }
+
+    /// one lookup per request, at most once per [RESOLVE_INTERVAL]; ends with the receive loop
+    async fn run(self, mut requests: mpsc::Receiver<()>) {
+        while requests.recv().await.is_some() {
+            self.resolve().await;
+            tokio::time::sleep(RESOLVE_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::{net::UdpSocket, sync::mpsc};
+
+    use crate::config::PushConfig;
+
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use crate::data::{ServiceHolder, model::{Instance, ServiceInfo}};
+
+    use super::{PushCounter, PushGuard, PushPacket, PushReceiver, SourceResolver};
+
+    #[tokio::test]
+    async fn test_bind_fallback() {
+        let taken = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        let fixed = PushConfig {
+            bind_ip: "127.0.0.1".to_owned(),
+            port: Some(port),
+            ..Default::default()
+        };
+        assert!(PushReceiver::bind(&fixed).await.is_err(), "port {} is taken", port);
+
+        let random = PushConfig {
+            bind_ip: "127.0.0.1".to_owned(),
+            ..Default::default()
+        };
+        let sock = PushReceiver::bind(&random).await.unwrap();
+        assert_ne!(sock.local_addr().unwrap().port(), port);
+    }
+
+    #[tokio::test]
+    async fn test_push_guard() {
+        let servers = vec!["http://127.0.0.1:8848/nacos".to_owned()];
+        let resolver = SourceResolver::new(&servers).unwrap();
+        resolver.resolve().await;
+        let (tx, mut rx) = mpsc::channel(1);
+        let guard = PushGuard::new(resolver.allowed.clone(), Some(tx));
+        assert!(guard.accept_source("127.0.0.1".parse().unwrap()));
+        assert!(!guard.accept_source("10.0.0.1".parse().unwrap()));
+        assert!(rx.try_recv().is_ok(), "unknown source asks for a lookup");
+
+        let open = PushGuard::new(Default::default(), None);
+        assert!(open.accept_source("10.0.0.1".parse().unwrap()));
+    }
+
+    fn push(info: &ServiceInfo) -> PushPacket {
+        PushPacket {
+            push_type: super::PUSH_TYPE_DOM.to_owned(),
+            last_ref_time: info.last_ref_time,
+            data: serde_json::to_string(info).unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forged_push() {
+        let dir = std::env::temp_dir().join(format!("nacos-push-forged-{}", std::process::id()));
+        let holder = ServiceHolder::new(&dir, false, false, Default::default()).await.unwrap();
+        let counter = PushCounter::default();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        let info = |ip: &str, last_ref_time: u64| {
+            let mut info = ServiceInfo::empty("DEFAULT_GROUP@@test", "DEFAULT");
+            info.hosts = vec![Instance::new_with_defaults("test", ip, 8080)];
+            info.last_ref_time = last_ref_time;
+            info
+        };
+        let hosts = || async {
+            holder.get_service_info("DEFAULT_GROUP@@test", &["DEFAULT"]).await.map(|info| info.hosts[0].ip.clone())
+        };
+
+        let open = PushGuard::new(Default::default(), None);
+        PushReceiver::build_reply(push(&info("10.0.0.66", u64::MAX)), &holder, &open, &counter).await;
+        assert_eq!(hosts().await, None, "pushes from the future are rejected");
+        // unverified pushes are applied in any order, a forged one can't block the real ones
+        PushReceiver::build_reply(push(&info("10.0.0.66", now + 60_000)), &holder, &open, &counter).await;
+        PushReceiver::build_reply(push(&info("127.0.0.1", now)), &holder, &open, &counter).await;
+        assert_eq!(hosts().await.as_deref(), Some("127.0.0.1"));
+
+        let (tx, _rx) = mpsc::channel(1);
+        let verified = PushGuard::new(Default::default(), Some(tx));
+        PushReceiver::build_reply(push(&info("10.0.0.66", now + 60_000)), &holder, &verified, &counter).await;
+        PushReceiver::build_reply(push(&info("127.0.0.1", now + 1)), &holder, &verified, &counter).await;
+        assert_eq!(hosts().await.as_deref(), Some("10.0.0.66"), "verified pushes are ordered");
+        // a poll of the server resets the watermark
+        assert!(holder.update_service_info(info("127.0.0.1", now + 2)).await);
+        PushReceiver::build_reply(push(&info("127.0.0.2", now + 3)), &holder, &verified, &counter).await;
+        assert_eq!(hosts().await.as_deref(), Some("127.0.0.2"));
+        assert_eq!(counter.rejected.load(std::sync::atomic::Ordering::Relaxed), 2);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
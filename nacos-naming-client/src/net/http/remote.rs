@@ -1,24 +1,28 @@
 use std::{sync::Arc, time::Duration};
 
 use crate::{
-    net::NamingRemote,
-    error::Result, 
+    config::{NamingConfig, PushConfig},
+    net::{NamingRemote, TokenSource},
+    error::{Error, ConfigError, Result}, 
     data::{
-        model::{Instance, ServiceInfo, Service, ExpressionSelector, Token, BeatAck, BeatRequest}, 
-        ServiceHolder, AccessTokenHolder
-    }
+        model::{Instance, Cluster, ServiceInfo, Service, ExpressionSelector, Token, BeatAck, BeatRequest}, 
+        ServiceUpdater
+    },
+    monitor::Monitor,
+    util::TaskGroup,
+    trace
 };
 use async_trait::async_trait;
 use itertools::Itertools;
 use reqwest::Method;
 use serde::Serialize;
-use tokio::sync::Mutex;
 
-use super::{client::HttpClient, push_receiver::PushReceiver};
+use super::{UpdaterSlot, client::HttpClient, push_receiver::{PushReceiver, PushStats}};
 
 const LOGIN_PATH: &str = "/v1/auth/users/login";
 const INSTANCE_PATH: &str = "/v1/ns/instance";
 const SERVICE_PATH: &str = "/v1/ns/service";
+const CLUSTER_PATH: &str = "/v1/ns/cluster";
 
 
 #[derive(Debug, Serialize)]
@@ -63,6 +67,22 @@ struct DeregisterRequest {
     pub ephemeral: bool,
 }
 
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")] 
+struct ClusterRequest {
+    pub namespace_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_token: Option<String>,
+    pub service_name: String,
+    pub cluster_name: String,
+    pub check_port: u16,
+    #[serde(rename = "useInstancePort4Check")]
+    pub use_instance_port: bool,
+    /// json of the health checker
+    pub health_checker: String,
+    pub metadata: String
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")] 
 pub struct QueryInstanceRequest {
@@ -104,28 +124,67 @@ pub struct ServiceListRequest {
 #[derive(Clone)]
 pub struct HttpNamingRemote {
     client: HttpClient,
-    service_holder: ServiceHolder,
-    receiver: Arc<Mutex<PushReceiver>>,
+    updater: UpdaterSlot,
+    receiver: Option<Arc<PushReceiver>>,
     address: Vec<String>,
     receiver_port: u16,
-    client_ip: String
+    client_ip: String,
+    /// the push receiver and the subscribe pollers
+    tasks: TaskGroup,
+    /// shared by the clones outside of `tasks`, the tasks are closed when the last of them is dropped
+    _closer: Option<Arc<TaskCloser>>
+}
+
+struct TaskCloser(TaskGroup);
+
+impl Drop for TaskCloser {
+    fn drop(&mut self) {
+        self.0.close()
+    }
 }
 
 impl HttpNamingRemote {
-    pub async fn new(
-        addresses: Vec<String>,
-        service_holder: ServiceHolder
-    ) -> Self {
-        let udp_port = rand::random::<u16>() % 1000 + 54951;
-        let receiver = PushReceiver::new(udp_port, service_holder.clone()).await;
+    /// a remote over the servers and push settings of `config`, e.g. to be boxed as `Arc<dyn NamingRemote>`
+    /// and passed to [crate::NamingClient::with_remote]
+    pub async fn from_config(config: &NamingConfig) -> Result<Self> {
+        config.validate()?;
+        let addresses = config.server_list.iter().map(|server| server.to_string()).collect();
+        Self::new(addresses, &config.push).await
+    }
+
+    /// `addresses` are `{scheme}://{host}:{port}/{context_path}`;
+    /// pushes and subscriptions reach the client this remote is attached to
+    pub async fn new(addresses: Vec<String>, push_config: &PushConfig) -> Result<Self> {
+        if addresses.is_empty() {
+            return Err(ConfigError::EmptyServerList.into());
+        }
+        let client_ip = match push_config.client_ip.as_ref() {
+            Some(ip) => ip.clone(),
+            None => local_ipaddress::get()
+                .ok_or_else(|| Error::Custom("can not obtain local ip, please set client_ip".to_owned()))?
+        };
+        let tasks = TaskGroup::new();
+        let updater = UpdaterSlot::default();
+        // udp port 0 tells nacos not to push to this client
+        let (receiver, udp_port) = if push_config.enabled {
+            let receiver = PushReceiver::new(
+                push_config, &addresses, updater.clone(), &tasks
+            ).await?;
+            let udp_port = receiver.udp_port();
+            (Some(Arc::new(receiver)), udp_port)
+        } else {
+            (None, 0)
+        };
 
         let remote = Self {
-            client: HttpClient::new(),
+            client: HttpClient::new(Monitor::default()),
             address: addresses,
-            receiver: Arc::new(Mutex::new(receiver)),
+            receiver,
             receiver_port: udp_port,
-            service_holder,
-            client_ip: local_ipaddress::get().unwrap()
+            updater,
+            client_ip,
+            _closer: Some(Arc::new(TaskCloser(tasks.clone()))),
+            tasks
         };
 
         log::info!(
@@ -134,11 +193,15 @@ impl HttpNamingRemote {
             remote.client_ip,
             remote.receiver_port
         );
-        remote
+        Ok(remote)
     }
 
-    pub async fn shutdown(&self) {
-        self.receiver.lock().await.shutdown().await
+    /// udp push counters; all zero if push is disabled
+    pub async fn push_stats(&self) -> PushStats {
+        match self.receiver.as_ref() {
+            Some(receiver) => receiver.stats(),
+            None => PushStats::default()
+        }
     }
 }
 
@@ -146,55 +209,62 @@ impl HttpNamingRemote {
 #[async_trait]
 impl NamingRemote for HttpNamingRemote {
     async fn login(&self, username: &str, password: &str) -> Result<Token> {
-        self.client.request_json(
-            &self.address,
-            LOGIN_PATH,
-            Method::POST, 
-            &Login {username, password}
-        ).await
+        let request = Login {username, password};
+        let call = self.client.request_json(&self.address, LOGIN_PATH, Method::POST, &request);
+        trace::remote_call(call, "login", "", "").await
     }
     /// 注册服务实例
     async fn register_instance(&self, namespace_id: &str, token: Option<String>, instance: Instance) -> Result<()> {
-        self.client.request_str(
-            &self.address,
-            INSTANCE_PATH,
-            Method::POST, 
-            &RegisterRequest::from_instance(namespace_id.to_string(), token, instance),
-        )
-        .await
-        .map(|_| ())
+        let service_name = instance.service_name.clone();
+        let request = RegisterRequest::from_instance(namespace_id.to_string(), token, instance);
+        let call = self.client.request_str(&self.address, INSTANCE_PATH, Method::POST, &request);
+        trace::remote_call(call, "register_instance", namespace_id, service_name.as_str())
+            .await
+            .map(|_| ())
     }
     /// 注销服务实例
     async fn deregister_instance(&self, namespace_id: &str, token: Option<String>, instance: Instance) -> Result<()> {
-        self.client.request_str(
-            &self.address,
-            INSTANCE_PATH,
-            Method::DELETE, 
-            &DeregisterRequest {
-                namespace_id: namespace_id.to_string(),
-                access_token: token,
-                service_name: instance.service_name,
-                cluster_name: instance.cluster_name,
-                ip: instance.ip,
-                port: instance.port,
-                ephemeral: instance.ephemeral
-            }
-        )
-        .await
-        .map(|_| ())
+        let request = DeregisterRequest {
+            namespace_id: namespace_id.to_string(),
+            access_token: token,
+            service_name: instance.service_name,
+            cluster_name: instance.cluster_name,
+            ip: instance.ip,
+            port: instance.port,
+            ephemeral: instance.ephemeral
+        };
+        let call = self.client.request_str(&self.address, INSTANCE_PATH, Method::DELETE, &request);
+        trace::remote_call(call, "deregister_instance", namespace_id, request.service_name.as_str())
+            .await
+            .map(|_| ())
     }
     /// 更新实例信息
     async fn update_instance(
         &self, namespace_id: &str, token: Option<String>, instance: Instance
     ) -> Result<()> {
-        self.client.request_str(
-            &self.address,
-            INSTANCE_PATH,
-            Method::PUT, 
-            &RegisterRequest::from_instance(namespace_id.to_string(), token, instance),
-        )
-        .await
-        .map(|_| ())
+        let service_name = instance.service_name.clone();
+        let request = RegisterRequest::from_instance(namespace_id.to_string(), token, instance);
+        let call = self.client.request_str(&self.address, INSTANCE_PATH, Method::PUT, &request);
+        trace::remote_call(call, "update_instance", namespace_id, service_name.as_str())
+            .await
+            .map(|_| ())
+    }
+    /// 更新集群配置
+    async fn update_cluster(&self, namespace_id: &str, token: Option<String>, cluster: Cluster) -> Result<()> {
+        let request = ClusterRequest {
+            namespace_id: namespace_id.to_string(),
+            access_token: token,
+            service_name: cluster.service_name,
+            cluster_name: cluster.cluster_name,
+            check_port: cluster.check_port,
+            use_instance_port: cluster.use_instance_port,
+            health_checker: serde_json::to_string(&cluster.health_checker)?,
+            metadata: serde_json::to_string(&cluster.metadata)?
+        };
+        let call = self.client.request_str(&self.address, CLUSTER_PATH, Method::PUT, &request);
+        trace::remote_call(call, "update_cluster", namespace_id, request.service_name.as_str())
+            .await
+            .map(|_| ())
     }
     /// 查找实例
     async fn query_instances(
@@ -204,22 +274,20 @@ impl NamingRemote for HttpNamingRemote {
         clusters: &[&str], healthy_only: bool
     ) -> Result<ServiceInfo> {
         let clusters = clusters.iter().join(",");
-        self.client.request_json(
-            &self.address,
-            format!("{}/{}", INSTANCE_PATH, "list").as_str(),
-            Method::GET, 
-            &QueryInstanceRequest {
-                namespace_id: namespace_id.to_string(),
-                access_token: token,
-                service_name,
-                //TODO fixme
-                group_name: "DEFAULT_GROUP".to_owned(),
-                clusters,
-                udp_port: self.receiver_port,
-                client_ip: self.client_ip.clone(),
-                healthy_only
-            }
-        ).await
+        let request = QueryInstanceRequest {
+            namespace_id: namespace_id.to_string(),
+            access_token: token,
+            service_name,
+            //TODO fixme
+            group_name: "DEFAULT_GROUP".to_owned(),
+            clusters,
+            udp_port: self.receiver_port,
+            client_ip: self.client_ip.clone(),
+            healthy_only
+        };
+        let path = format!("{}/{}", INSTANCE_PATH, "list");
+        let call = self.client.request_json(&self.address, path.as_str(), Method::GET, &request);
+        trace::remote_call(call, "query_instances", namespace_id, request.service_name.as_str()).await
     }
     /// 创建新服务
     //fn create_service(&self, );
@@ -232,17 +300,13 @@ impl NamingRemote for HttpNamingRemote {
         &self, namespace_id: &str, 
         token: Option<String>, service_name: String
     ) -> Result<Service> {
-        self.client.request_json(
-            &self.address,
-            SERVICE_PATH,
-            Method::GET, 
-            &QueryServiceRequest {
-                namespace_id: namespace_id.to_string(),
-                access_token: token,
-                service_name
-            }
-        ).await
-        
+        let request = QueryServiceRequest {
+            namespace_id: namespace_id.to_string(),
+            access_token: token,
+            service_name
+        };
+        let call = self.client.request_json(&self.address, SERVICE_PATH, Method::GET, &request);
+        trace::remote_call(call, "query_service", namespace_id, request.service_name.as_str()).await
     }
     /// 查找所有服务
     async fn query_all_service(
@@ -256,40 +320,36 @@ impl NamingRemote for HttpNamingRemote {
         let selector = selector.map(|se| serde_json::to_string(&se)
             .expect("can not serialize selector"));
         
-        self.client.request_json(
-            &self.address,
-            SERVICE_PATH,
-            Method::GET, 
-            &ServiceListRequest {
-                namespace_id: namespace_id.to_string(),
-                access_token: token,
-                group_name: group_name.to_string(),
-                page_no: page_num,
-                page_size,
-                selector
-            }
-        ).await
+        let request = ServiceListRequest {
+            namespace_id: namespace_id.to_string(),
+            access_token: token,
+            group_name: group_name.to_string(),
+            page_no: page_num,
+            page_size,
+            selector
+        };
+        let call = self.client.request_json(&self.address, SERVICE_PATH, Method::GET, &request);
+        trace::remote_call(call, "query_all_service", namespace_id, "").await
     }
 
     async fn beat(&self, info: &BeatRequest) -> Result<BeatAck> {
-        self.client.request_json(
-            &self.address,
-            format!("{}/{}", INSTANCE_PATH, "beat").as_str(),
-            Method::PUT, 
-            &info
-        ).await
+        let path = format!("{}/{}", INSTANCE_PATH, "beat");
+        let call = self.client.request_json(&self.address, path.as_str(), Method::PUT, &info);
+        trace::remote_call(call, "beat", info.namespace_id.as_str(), info.service_name.as_str()).await
     }
 
     /// 订阅服务信息变化通知
-    async fn subscribe<R: NamingRemote + 'static>(
-        &self, namespace_id: &str, token: AccessTokenHolder<R>,
+    async fn subscribe(
+        &self, namespace_id: &str, token: Arc<dyn TokenSource>,
         service_name: &str, clusters: &[&str]
     ) -> Result<()> {
-        let remote = self.clone();
+        // the poller must not keep the remote from being closed on drop
+        let remote = Self { _closer: None, ..self.clone() };
         let namespace_id = namespace_id.to_string();
         let service_name = service_name.to_string();
         let cluster_vec = clusters.iter().map(|cluster| cluster.to_string()).collect::<Vec<_>>();
-        tokio::spawn(async move {
+        let target = service_name.clone();
+        let task = async move {
             let clusters = &cluster_vec.iter().map(|cluster| cluster.as_str()).collect::<Vec<_>>()[..];
             loop {
                 let myabe_token = token.get_token().await;
@@ -297,7 +357,15 @@ impl NamingRemote for HttpNamingRemote {
                     namespace_id.as_str(), myabe_token, service_name.clone(), clusters, false
                 ).await;
                 match service_info {
-                    Ok(info) => remote.service_holder.update_service_info(info).await,
+                    Ok(info) => {
+                        let updater = remote.updater.read().expect("[http] lock poisoned").clone();
+                        match updater {
+                            Some(updater) => {
+                                updater.update(info).await;
+                            },
+                            None => log::debug!("no client attached, drop service info: {}", service_name)
+                        }
+                    },
                     Err(error) => log::error!("failed to subscribe service: {}; cause: {}", service_name, error)
                 }
                 log::debug!("wait for next query: {}", service_name);
@@ -305,7 +373,8 @@ impl NamingRemote for HttpNamingRemote {
                 log::debug!("continue to query: {}", service_name);
 
             }
-        });
+        };
+        self.tasks.spawn(trace::background(task, "subscribe", target.as_str()));
         Ok(())
     }
     
@@ -315,6 +384,25 @@ impl NamingRemote for HttpNamingRemote {
     ) -> Result<()> {
         Ok(())
     }
+
+    /// 推送和轮询到的服务信息写入客户端缓存，请求指标记录到客户端的监控中
+    fn attach(&self, updater: ServiceUpdater) {
+        self.client.set_monitor(updater.monitor().clone());
+        if let Some(receiver) = self.receiver.as_ref() {
+            receiver.set_monitor(updater.monitor().clone());
+        }
+        *self.updater.write().expect("[http] lock poisoned") = Some(updater);
+    }
+
+    /// 停止udp接收和订阅轮询
+    fn close(&self) {
+        self.tasks.close()
+    }
+
+    async fn shutdown(&self) {
+        self.tasks.close();
+        self.tasks.join().await
+    }
 }
 
 impl RegisterRequest {
@@ -1,7 +1,15 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use nacos_mock_server::{MockServer, MockInstance};
+use tokio::sync::mpsc;
+
 use crate::{
-    net::{http::HttpNamingRemote, NamingRemote}, 
+    net::{http::HttpNamingRemote, NamingRemote, TokenSource}, 
     data::model::Instance,
-    error::Result
+    config::{NamingConfig, PushConfig},
+    error::{Error, Result},
+    NamingClient, ServiceChangeListener, ShutdownOptions
 };
 
 fn init_logger() {
@@ -14,21 +22,172 @@ fn init_logger() {
         .try_init();
 }
 
+async fn http_remote(server: &MockServer) -> Result<HttpNamingRemote> {
+    let push = PushConfig {
+        client_ip: Some("127.0.0.1".to_owned()),
+        ..PushConfig::disabled()
+    };
+    HttpNamingRemote::new(vec![server.url()], &push).await
+}
+
 #[tokio::test]
 async fn test_http_remote_register() -> Result<()> {
     init_logger();
-    let http_remote = HttpNamingRemote::new("http://192.168.1.248:8848/nacos", 8765);
-    http_remote.register_instance("public", Instance::new_with_defaults("test", "192.168.1.221", 8888)).await?;
+    let server = MockServer::start().await.unwrap();
+    let http_remote = http_remote(&server).await?;
+    let instance = Instance::new_with_defaults("test", "127.0.0.1", 8888);
+    http_remote.register_instance("public", None, instance.clone()).await?;
     let info = http_remote.query_instances(
-        "public", "DEFAULT_GROUP@@test".to_string(), &["DEFAULT"], false
+        "public", None, "DEFAULT_GROUP@@test".to_string(), &["DEFAULT"], false
     ).await?;
     assert!(info.hosts.len() == 1, "after register; hosts len: {}", info.hosts.len());
 
-    http_remote.deregister_instance("public", Instance::new_with_defaults("test", "192.168.1.221", 8888)).await?;
+    http_remote.deregister_instance("public", None, instance).await?;
     let info = http_remote.query_instances(
-        "public", "DEFAULT_GROUP@@test".to_string(), &["DEFAULT"], false
+        "public", None, "DEFAULT_GROUP@@test".to_string(), &["DEFAULT"], false
     ).await?;
     
-    assert!(info.hosts.len() == 0, "after deregister; hosts len: {}", info.hosts.len());
+    assert!(info.hosts.is_empty(), "after deregister; hosts len: {}", info.hosts.len());
+    server.shutdown().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_http_remote_auth() -> Result<()> {
+    init_logger();
+    let server = MockServer::builder().auth("nacos", "nacos").start().await.unwrap();
+    let http_remote = http_remote(&server).await?;
+    // auth failures try the other servers too, the error of the last one is returned
+    let auth_failed = |error: Error| matches!(error, Error::Auth(_)) && !error.is_retryable();
+    assert!(auth_failed(http_remote.login("nacos", "wrong").await.unwrap_err()));
+    let instance = Instance::new_with_defaults("test", "127.0.0.1", 8888);
+    assert!(auth_failed(http_remote.register_instance("public", None, instance.clone()).await.unwrap_err()));
+
+    let token = http_remote.login("nacos", "nacos").await?;
+    http_remote.register_instance("public", Some(token.access_token), instance).await?;
+    assert_eq!(server.instances("public", "DEFAULT_GROUP", "test").len(), 1);
+
+    server.set_available(false);
+    let error = http_remote.query_service("public", None, "DEFAULT_GROUP@@test".to_owned()).await.unwrap_err();
+    assert!(matches!(error, Error::AllServersFailed(_)) && error.is_retryable());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_http_remote_token_expiry() -> Result<()> {
+    init_logger();
+    let server = MockServer::builder().auth("nacos", "nacos").token_ttl(1).start().await.unwrap();
+    let http_remote = http_remote(&server).await?;
+    let instance = Instance::new_with_defaults("test", "127.0.0.1", 8888);
+    let token = http_remote.login("nacos", "nacos").await?;
+    http_remote.register_instance("public", Some(token.access_token.clone()), instance.clone()).await?;
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let error = http_remote.register_instance("public", Some(token.access_token), instance.clone()).await.unwrap_err();
+    assert!(error.server_error().is_some_and(|error| error.status == reqwest::StatusCode::FORBIDDEN));
+    let token = http_remote.login("nacos", "nacos").await?;
+    http_remote.register_instance("public", Some(token.access_token), instance).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_http_remote_failover() -> Result<()> {
+    init_logger();
+    let busy = MockServer::start().await.unwrap();
+    busy.set_available(false);
+    let stopped = MockServer::start().await.unwrap();
+    let stopped_url = stopped.url();
+    stopped.shutdown().await;
+    let server = MockServer::start().await.unwrap();
+
+    let push = PushConfig { client_ip: Some("127.0.0.1".to_owned()), ..PushConfig::disabled() };
+    let http_remote = HttpNamingRemote::new(vec![busy.url(), stopped_url, server.url()], &push).await?;
+    // the first server is random, every start reaches the available one
+    for port in 8000..8010 {
+        http_remote.register_instance("public", None, Instance::new_with_defaults("test", "127.0.0.1", port)).await?;
+    }
+    assert_eq!(server.instances("public", "DEFAULT_GROUP", "test").len(), 10);
+    assert!(busy.instances("public", "DEFAULT_GROUP", "test").is_empty());
+
+    server.set_available(false);
+    let error = http_remote.query_service("public", None, "DEFAULT_GROUP@@test".to_owned()).await.unwrap_err();
+    assert!(matches!(&error, Error::AllServersFailed(errors) if errors.len() == 3) && error.is_retryable());
+    Ok(())
+}
+
+struct NoToken;
+
+#[async_trait]
+impl TokenSource for NoToken {
+    async fn get_token(&self) -> Option<String> {
+        None
+    }
+}
+
+struct Listener(mpsc::UnboundedSender<Vec<Instance>>);
+
+#[async_trait]
+impl ServiceChangeListener for Listener {
+    async fn changed(&self, _: &str, hosts: Vec<Instance>) {
+        let _ = self.0.send(hosts);
+    }
+}
+
+#[tokio::test]
+async fn test_http_remote_drop() -> Result<()> {
+    init_logger();
+    let server = MockServer::start().await.unwrap();
+    let port = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let push = PushConfig {
+        client_ip: Some("127.0.0.1".to_owned()),
+        bind_ip: "127.0.0.1".to_owned(),
+        port: Some(port),
+        ..PushConfig::default()
+    };
+    let http_remote = HttpNamingRemote::new(vec![server.url()], &push).await?;
+    http_remote.subscribe("public", Arc::new(NoToken), "test", &[]).await?;
+    drop(http_remote);
+    // the receive task has ended and closed its socket, although the poller is still sleeping
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while tokio::net::UdpSocket::bind((push.bind_ip.as_str(), port)).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }).await.expect("push receiver not closed");
+    server.shutdown().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dyn_http_remote() -> Result<()> {
+    init_logger();
+    let server = MockServer::start().await.unwrap();
+    let cache_dir = std::env::temp_dir().join(format!("nacos-remote-dyn-{}", std::process::id()));
+    let config = NamingConfig::builder()
+        .server_addr(server.url().as_str())
+        .cache_dir(cache_dir.to_str().unwrap())
+        .push(PushConfig { client_ip: Some("127.0.0.1".to_owned()), ..PushConfig::default() })
+        .build()?;
+    let remote: Arc<dyn NamingRemote> = Arc::new(HttpNamingRemote::from_config(&config).await?);
+    let client = NamingClient::with_remote(config, remote).await?;
+
+    client.register_instance(Instance::new_with_defaults("test", "127.0.0.1", 8888)).await?;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    client.subscribe("test", "DEFAULT_GROUP", vec!["DEFAULT"], Listener(tx)).await?;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while server.subscribers("public", "DEFAULT_GROUP", "test") == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }).await.expect("no subscriber");
+    // the push reaches the cache of the client through the attached updater
+    server.register_instance("public", "DEFAULT_GROUP", "test", MockInstance::new("127.0.0.2", 8888)).await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while rx.recv().await.map(|hosts| hosts.len()) != Some(2) {}
+    }).await.expect("no push received");
+    assert_eq!(client.select_instances("test", "DEFAULT_GROUP", vec!["DEFAULT"], true).await?.len(), 2);
+
+    client.shutdown(ShutdownOptions::default()).await?;
+    assert_eq!(server.instances("public", "DEFAULT_GROUP", "test").len(), 1);
+    server.shutdown().await;
+    let _ = std::fs::remove_dir_all(cache_dir);
     Ok(())
 }
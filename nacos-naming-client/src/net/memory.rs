@@ -0,0 +1,412 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH}
+};
+
+use async_trait::async_trait;
+use itertools::Itertools;
+use reqwest::StatusCode;
+
+use crate::{
+    constants,
+    net::{NamingRemote, TokenSource},
+    error::{Error, RespCode, Result},
+    data::{
+        model::{Instance, Cluster, ServiceInfo, Service, ExpressionSelector, Token, BeatAck, BeatRequest},
+        ServiceUpdater
+    }
+};
+
+/// the calls of [NamingRemote], to inject failures into and count the calls of an [InMemoryNamingRemote];
+/// the batch calls count as one call per instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RemoteCall {
+    Login,
+    RegisterInstance,
+    DeregisterInstance,
+    UpdateInstance,
+    UpdateCluster,
+    QueryInstances,
+    QueryService,
+    QueryAllService,
+    Beat,
+    Subscribe,
+    Unsubscribe
+}
+
+/// `clientBeatInterval` of a nacos server with the default settings
+const DEFAULT_BEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// key: (namespace_id, {group}@@{name})
+type ServiceKey = (String, String);
+
+#[derive(Default)]
+struct State {
+    /// instances by ip:port
+    services: HashMap<ServiceKey, BTreeMap<String, Instance>>,
+    /// the clusters of every subscription
+    subscriptions: HashMap<ServiceKey, HashSet<String>>,
+    failures: HashMap<RemoteCall, VecDeque<Error>>,
+    calls: HashMap<RemoteCall, usize>,
+    last_ref_time: u64,
+    /// `clientBeatInterval` of the beat acks, [DEFAULT_BEAT_INTERVAL] if none
+    beat_interval: Option<Duration>
+}
+
+#[derive(Default)]
+struct Inner {
+    state: Mutex<State>,
+    updater: Mutex<Option<ServiceUpdater>>
+}
+
+/// a registry kept in memory instead of a nacos server, for tests of code using [crate::NamingClient]:
+/// create the client with [crate::NamingClient::with_remote] and keep a clone to inject instance changes
+/// and failures. every change of a subscribed service reaches the listeners before the call returns.
+/// only built with the `testing` feature; auth, selectors and health checks are not simulated
+#[derive(Clone, Default)]
+pub struct InMemoryNamingRemote {
+    inner: Arc<Inner>
+}
+
+impl InMemoryNamingRemote {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the next `call` fails with `error`; errors queued for the same call fail the calls one by one
+    pub fn fail_next(&self, call: RemoteCall, error: Error) {
+        self.state().failures.entry(call).or_default().push_back(error);
+    }
+
+    /// `clientBeatInterval` returned by beats, 5s by default; the client beats 2s earlier, at least every second
+    pub fn set_beat_interval(&self, interval: Duration) {
+        self.state().beat_interval = Some(interval);
+    }
+
+    /// number of `call`s so far, including the failed ones
+    pub fn calls(&self, call: RemoteCall) -> usize {
+        self.state().calls.get(&call).copied().unwrap_or_default()
+    }
+
+    /// instances of a service, sorted by `ip:port`
+    pub fn instances(&self, namespace_id: &str, service_name: &str, group_name: &str) -> Vec<Instance> {
+        let key = service_key(namespace_id, service_name, group_name);
+        self.state().services.get(&key)
+            .map(|instances| instances.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn is_subscribed(&self, namespace_id: &str, service_name: &str, group_name: &str) -> bool {
+        let key = service_key(namespace_id, service_name, group_name);
+        self.state().subscriptions.get(&key).is_some_and(|clusters| !clusters.is_empty())
+    }
+
+    /// add or replace an instance, like another client registering it
+    pub async fn put_instance(&self, namespace_id: &str, instance: Instance) {
+        let key = (namespace_id.to_string(), instance.service_name.clone());
+        self.state().services.entry(key.clone()).or_default().insert(instance.key(), instance);
+        self.publish(key).await
+    }
+
+    /// remove an instance, like another client deregistering it or the server expiring it
+    pub async fn remove_instance(&self, namespace_id: &str, instance: &Instance) {
+        let key = (namespace_id.to_string(), instance.service_name.clone());
+        if let Some(instances) = self.state().services.get_mut(&key) {
+            instances.remove(instance.key().as_str());
+        }
+        self.publish(key).await
+    }
+
+    /// replace all instances of a service
+    pub async fn set_instances(&self, namespace_id: &str, service_name: &str, group_name: &str, hosts: Vec<Instance>) {
+        let key = service_key(namespace_id, service_name, group_name);
+        let instances = hosts.into_iter()
+            .map(|mut instance| {
+                instance.service_name = key.1.clone();
+                instance.group_name = group_name.to_string();
+                (instance.key(), instance)
+            })
+            .collect();
+        self.state().services.insert(key.clone(), instances);
+        self.publish(key).await
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.inner.state.lock().expect("[memory] lock poisoned")
+    }
+
+    /// count the call and take the injected failure
+    fn check(&self, call: RemoteCall) -> Result<()> {
+        let mut state = self.state();
+        *state.calls.entry(call).or_default() += 1;
+        match state.failures.get_mut(&call).and_then(VecDeque::pop_front) {
+            Some(error) => Err(error),
+            None => Ok(())
+        }
+    }
+
+    /// send the service to the client for every subscribed cluster list
+    async fn publish(&self, key: ServiceKey) {
+        let infos = {
+            let mut state = self.state();
+            let clusters = match state.subscriptions.get(&key) {
+                Some(clusters) if !clusters.is_empty() => clusters.clone(),
+                _ => return
+            };
+            state.last_ref_time = now_millis().max(state.last_ref_time + 1);
+            clusters.iter()
+                .map(|clusters| state.service_info(&key, clusters.as_str(), false))
+                .collect::<Vec<_>>()
+        };
+        let updater = self.inner.updater.lock().expect("[memory] lock poisoned").clone();
+        if let Some(updater) = updater {
+            for info in infos {
+                updater.update(info).await;
+            }
+        }
+    }
+
+    fn not_found(message: String) -> Error {
+        Error::from_response(StatusCode::NOT_FOUND, message)
+    }
+}
+
+impl State {
+    fn service_info(&self, key: &ServiceKey, clusters: &str, healthy_only: bool) -> ServiceInfo {
+        let wanted = clusters.split(',').filter(|cluster| !cluster.is_empty()).collect::<Vec<_>>();
+        let mut info = ServiceInfo::empty(key.1.as_str(), clusters);
+        info.last_ref_time = self.last_ref_time;
+        info.hosts = self.services.get(key)
+            .map(|instances| instances.values()
+                .filter(|instance| wanted.is_empty() || wanted.contains(&instance.cluster_name.as_str()))
+                .filter(|instance| !healthy_only || instance.healthy)
+                .cloned()
+                .collect())
+            .unwrap_or_default();
+        info
+    }
+}
+
+fn service_key(namespace_id: &str, service_name: &str, group_name: &str) -> ServiceKey {
+    (namespace_id.to_string(), crate::util::grouped_service_name(service_name, group_name))
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_millis() as u64).unwrap_or_default()
+}
+
+#[async_trait]
+impl NamingRemote for InMemoryNamingRemote {
+    async fn login(&self, _: &str, _: &str) -> Result<Token> {
+        self.check(RemoteCall::Login)?;
+        Ok(Token { access_token: "in-memory".to_owned(), token_ttl: 18000 })
+    }
+
+    async fn register_instance(&self, namespace_id: &str, _: Option<String>, instance: Instance) -> Result<()> {
+        self.check(RemoteCall::RegisterInstance)?;
+        self.put_instance(namespace_id, instance).await;
+        Ok(())
+    }
+
+    async fn deregister_instance(&self, namespace_id: &str, _: Option<String>, instance: Instance) -> Result<()> {
+        self.check(RemoteCall::DeregisterInstance)?;
+        self.remove_instance(namespace_id, &instance).await;
+        Ok(())
+    }
+
+    async fn update_instance(&self, namespace_id: &str, _: Option<String>, instance: Instance) -> Result<()> {
+        self.check(RemoteCall::UpdateInstance)?;
+        let key = (namespace_id.to_string(), instance.service_name.clone());
+        match self.state().services.get_mut(&key).and_then(|instances| instances.get_mut(instance.key().as_str())) {
+            Some(registered) => *registered = instance,
+            None => return Err(Self::not_found(format!("instance not found: {}", instance.key())))
+        }
+        self.publish(key).await;
+        Ok(())
+    }
+
+    async fn update_cluster(&self, _: &str, _: Option<String>, _: Cluster) -> Result<()> {
+        self.check(RemoteCall::UpdateCluster)
+    }
+
+    async fn query_instances(
+        &self, namespace_id: &str, _: Option<String>, service_name: String, clusters: &[&str], healthy_only: bool
+    ) -> Result<ServiceInfo> {
+        self.check(RemoteCall::QueryInstances)?;
+        let key = (namespace_id.to_string(), service_name);
+        Ok(self.state().service_info(&key, clusters.iter().join(",").as_str(), healthy_only))
+    }
+
+    async fn query_service(&self, namespace_id: &str, _: Option<String>, service_name: String) -> Result<Service> {
+        self.check(RemoteCall::QueryService)?;
+        let key = (namespace_id.to_string(), service_name);
+        if !self.state().services.contains_key(&key) {
+            return Err(Self::not_found(format!("service not found: {}", key.1)));
+        }
+        Ok(service(key.1.as_str()))
+    }
+
+    /// the selector is ignored, every service of the group is listed
+    async fn query_all_service(
+        &self,
+        namespace_id: &str, _: Option<String>,
+        group_name: &str,
+        _: Option<ExpressionSelector>,
+        page_num: u32, page_size: u32
+    ) -> Result<Vec<Service>> {
+        self.check(RemoteCall::QueryAllService)?;
+        let prefix = format!("{}{}", group_name, constants::SERVICE_INFO_SPLITER);
+        let state = self.state();
+        Ok(state.services.keys()
+            .filter(|(namespace, name)| namespace == namespace_id && name.starts_with(prefix.as_str()))
+            .map(|(_, name)| name.as_str())
+            .sorted()
+            .skip((page_num.max(1) - 1) as usize * page_size as usize)
+            .take(page_size as usize)
+            .map(service)
+            .collect())
+    }
+
+    /// like the server, a beat of an unknown instance registers it again
+    async fn beat(&self, info: &BeatRequest) -> Result<BeatAck> {
+        self.check(RemoteCall::Beat)?;
+        let key = (info.namespace_id.clone(), info.service_name.clone());
+        let beat = &info.beat_info;
+        let registered = self.state().services.get(&key)
+            .is_some_and(|instances| instances.contains_key(format!("{}:{}", beat.ip, beat.port).as_str()));
+        if !registered {
+            let (group_name, _) = info.service_name.split_once(constants::SERVICE_INFO_SPLITER)
+                .unwrap_or((constants::DEFAULT_GROUP, ""));
+            let mut instance = Instance::new_with_required("", group_name, beat.cluster.as_str(), beat.ip.as_str(), beat.port);
+            instance.service_name = info.service_name.clone();
+            instance.weight = beat.weight;
+            instance.metadata = beat.metadata.clone();
+            self.put_instance(info.namespace_id.as_str(), instance).await;
+        }
+        let interval = self.state().beat_interval.unwrap_or(DEFAULT_BEAT_INTERVAL);
+        Ok(BeatAck { client_beat_interval: interval.as_millis() as u64, code: Some(RespCode::Ok), light_beat_enabled: Some(true) })
+    }
+
+    /// the current instances reach the client before this returns
+    async fn subscribe(
+        &self, namespace_id: &str, _: Arc<dyn TokenSource>, service_name: &str, clusters: &[&str]
+    ) -> Result<()> {
+        self.check(RemoteCall::Subscribe)?;
+        let key = (namespace_id.to_string(), service_name.to_string());
+        self.state().subscriptions.entry(key.clone()).or_default().insert(clusters.iter().join(","));
+        self.publish(key).await;
+        Ok(())
+    }
+
+    async fn unsubscribe(
+        &self, namespace_id: &str, _: Option<String>, service_name: &str, clusters: &[&str]
+    ) -> Result<()> {
+        self.check(RemoteCall::Unsubscribe)?;
+        let key = (namespace_id.to_string(), service_name.to_string());
+        if let Some(subscribed) = self.state().subscriptions.get_mut(&key) {
+            subscribed.remove(clusters.iter().join(",").as_str());
+        }
+        Ok(())
+    }
+
+    fn attach(&self, updater: ServiceUpdater) {
+        *self.inner.updater.lock().expect("[memory] lock poisoned") = Some(updater);
+    }
+}
+
+fn service(grouped_name: &str) -> Service {
+    let (group_name, name) = grouped_name.split_once(constants::SERVICE_INFO_SPLITER)
+        .unwrap_or((constants::DEFAULT_GROUP, grouped_name));
+    Service {
+        name: name.to_string(),
+        group_name: group_name.to_string(),
+        app_name: String::new(),
+        protection_threshold: 0f32,
+        metadata: HashMap::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+
+    use crate::{
+        NamingClient, NamingConfig, NamingRemote, ServiceChangeListener, ShutdownOptions,
+        error::{Error, Result}, model::Instance
+    };
+
+    use super::{InMemoryNamingRemote, RemoteCall};
+
+    #[derive(Clone, Default)]
+    struct Listener(Arc<Mutex<Vec<Vec<Instance>>>>);
+
+    #[async_trait]
+    impl ServiceChangeListener for Listener {
+        async fn changed(&self, _: &str, hosts: Vec<Instance>) {
+            self.0.lock().unwrap().push(hosts);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_remote() -> Result<()> {
+        let cache_dir = std::env::temp_dir().join(format!("nacos-client-memory-{}", std::process::id()));
+        // the builder wants a server, the in-memory remote never connects to it
+        let config = NamingConfig::builder()
+            .server_addr("127.0.0.1:8848")
+            .cache_dir(cache_dir.to_str().unwrap())
+            .build()?;
+        let remote = InMemoryNamingRemote::new();
+        let client = NamingClient::with_remote(config, remote.clone()).await?;
+
+        remote.fail_next(RemoteCall::RegisterInstance, Error::Custom("injected".to_owned()));
+        let instance = Instance::new_with_defaults("test", "127.0.0.1", 8888);
+        assert!(client.register_instance(instance.clone()).await.is_err());
+        client.register_instance(instance).await?;
+        assert_eq!(remote.calls(RemoteCall::RegisterInstance), 2);
+
+        let listener = Listener::default();
+        client.subscribe("test", "DEFAULT_GROUP", vec!["DEFAULT"], listener.clone()).await?;
+        assert!(remote.is_subscribed("public", "test", "DEFAULT_GROUP"));
+        // the instance registered before the subscription
+        assert_eq!(listener.0.lock().unwrap().first().map(Vec::len), Some(1));
+        assert_eq!(client.select_instances("test", "DEFAULT_GROUP", vec!["DEFAULT"], true).await?.len(), 1);
+
+        // delivered before put_instance returns
+        let other = Instance::new_with_defaults("test", "127.0.0.2", 8888);
+        remote.put_instance("public", other.clone()).await;
+        assert_eq!(listener.0.lock().unwrap().last().map(Vec::len), Some(2));
+        remote.remove_instance("public", &other).await;
+        let removed = listener.0.lock().unwrap().last().unwrap().iter().filter(|host| !host.enabled).count();
+        assert_eq!(removed, 1);
+
+        client.shutdown(ShutdownOptions::default()).await?;
+        assert_eq!(remote.calls(RemoteCall::DeregisterInstance), 1);
+        let _ = std::fs::remove_dir_all(cache_dir);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dyn_remote() -> Result<()> {
+        let cache_dir = std::env::temp_dir().join(format!("nacos-client-dyn-{}", std::process::id()));
+        let config = NamingConfig::builder()
+            .server_addr("127.0.0.1:8848")
+            .cache_dir(cache_dir.to_str().unwrap())
+            .build()?;
+        let memory = InMemoryNamingRemote::new();
+        let remote: Arc<dyn NamingRemote> = Arc::new(memory.clone());
+        let client = NamingClient::with_remote(config, remote).await?;
+
+        client.register_instance(Instance::new_with_defaults("test", "127.0.0.1", 8888)).await?;
+        client.subscribe("test", "DEFAULT_GROUP", vec!["DEFAULT"], Listener::default()).await?;
+        assert!(memory.is_subscribed("public", "test", "DEFAULT_GROUP"));
+        assert_eq!(client.select_instances("test", "DEFAULT_GROUP", vec!["DEFAULT"], true).await?.len(), 1);
+
+        client.shutdown(ShutdownOptions::default()).await?;
+        assert!(memory.instances("public", "test", "DEFAULT_GROUP").is_empty());
+        let _ = std::fs::remove_dir_all(cache_dir);
+        Ok(())
+    }
+}
@@ -1,10 +1,24 @@
-use crate::data::AccessTokenHolder;
-use crate::data::model::{Instance, ExpressionSelector, Service, ServiceInfo, Token, BeatAck, BeatRequest};
-use crate::error::Result;
+use std::sync::Arc;
+
+use crate::data::ServiceUpdater;
+use crate::data::model::{Instance, Cluster, ExpressionSelector, Service, ServiceInfo, Token, BeatAck, BeatRequest};
+use crate::error::{Result, batch_result};
 use async_trait::async_trait;
+use futures::future::join_all;
 
 mod http;
-pub use http::HttpNamingRemote;
+#[cfg(any(test, feature = "testing"))]
+mod memory;
+pub use http::{HttpNamingRemote, PushStats};
+#[cfg(any(test, feature = "testing"))]
+pub use memory::{InMemoryNamingRemote, RemoteCall};
+
+/// 当前有效的accessToken，由客户端的token刷新任务维护
+#[async_trait]
+pub trait TokenSource: Send + Sync {
+    /// 未开启鉴权或尚未登录成功时为None
+    async fn get_token(&self) -> Option<String>;
+}
 
 /// 所有操作instance的service_name都是包含group信息的service_name: {group}@@{name}
 #[async_trait]
@@ -15,8 +29,27 @@ pub trait NamingRemote: Send + Sync {
     async fn register_instance(&self, namespace_id: &str, token: Option<String>, instance: Instance) -> Result<()>;
     /// 注销服务实例
     async fn deregister_instance(&self, namespace_id: &str, token: Option<String>, instance: Instance) -> Result<()>;
+    /// 批量注册同一服务的实例
+    /// http没有批量接口，默认并发注册并汇总失败的实例；grpc实现应映射为nacos 2.x的BatchInstanceRequest
+    async fn batch_register_instance(&self, namespace_id: &str, token: Option<String>, instances: Vec<Instance>) -> Result<()> {
+        let results = join_all(instances.into_iter().map(|instance| {
+            let token = token.clone();
+            async move { (instance.key(), self.register_instance(namespace_id, token, instance).await) }
+        })).await;
+        batch_result(results)
+    }
+    /// 批量注销同一服务的实例，默认并发注销并汇总失败的实例
+    async fn batch_deregister_instance(&self, namespace_id: &str, token: Option<String>, instances: Vec<Instance>) -> Result<()> {
+        let results = join_all(instances.into_iter().map(|instance| {
+            let token = token.clone();
+            async move { (instance.key(), self.deregister_instance(namespace_id, token, instance).await) }
+        })).await;
+        batch_result(results)
+    }
     /// 更新实例信息
     async fn update_instance(&self, namespace_id: &str, token: Option<String>, instance: Instance) -> Result<()>;
+    /// 更新集群配置，如持久化实例的健康检查方式
+    async fn update_cluster(&self, namespace_id: &str, token: Option<String>, cluster: Cluster) -> Result<()>;
     /// 查找实例
     async fn query_instances(
         &self, namespace_id: &str, token: Option<String>, service_name: String, clusters: &[&str], healthy_only: bool
@@ -41,14 +74,91 @@ pub trait NamingRemote: Send + Sync {
     async fn beat(&self, info: &BeatRequest) -> Result<BeatAck>;
 
     /// 订阅服务信息变化通知
-    /// 在httpRemote中因为需要轮询请求服务端保持udp端口在线，而token则可能变动，所以需要把token_holder传进去
-    async fn subscribe<R: NamingRemote + 'static>(
-        &self, namespace_id: &str, token: AccessTokenHolder<R>, service_name: &str, clusters: &[&str]
+    /// 在httpRemote中因为需要轮询请求服务端保持udp端口在线，而token则可能变动，所以需要把token来源传进去
+    async fn subscribe(
+        &self, namespace_id: &str, token: Arc<dyn TokenSource>, service_name: &str, clusters: &[&str]
     ) -> Result<()>;
     
     /// 退订服务信息变化通知
     async fn unsubscribe(
         &self, namespace_id: &str, token: Option<String>, service_name: &str, clusters: &[&str]
     ) -> Result<()>;
+
+    /// 接收客户端服务缓存的更新入口，NamingClient::with_remote创建客户端时调用；
+    /// 订阅到的服务变化应通过它更新缓存并通知监听者
+    fn attach(&self, _updater: ServiceUpdater) {}
+
+    /// 通知后台任务(如订阅轮询)停止，不等待其结束
+    fn close(&self) {}
+
+    /// 停止后台任务并等待其结束
+    async fn shutdown(&self) {
+        self.close()
+    }
 }
 
+
+/// 共享或装箱的remote，如`Arc<dyn NamingRemote>`，可在运行时选择传输方式或叠加装饰层
+#[async_trait]
+impl<T: NamingRemote + ?Sized> NamingRemote for Arc<T> {
+    async fn login(&self, username: &str, password: &str) -> Result<Token> {
+        (**self).login(username, password).await
+    }
+    async fn register_instance(&self, namespace_id: &str, token: Option<String>, instance: Instance) -> Result<()> {
+        (**self).register_instance(namespace_id, token, instance).await
+    }
+    async fn deregister_instance(&self, namespace_id: &str, token: Option<String>, instance: Instance) -> Result<()> {
+        (**self).deregister_instance(namespace_id, token, instance).await
+    }
+    async fn batch_register_instance(&self, namespace_id: &str, token: Option<String>, instances: Vec<Instance>) -> Result<()> {
+        (**self).batch_register_instance(namespace_id, token, instances).await
+    }
+    async fn batch_deregister_instance(&self, namespace_id: &str, token: Option<String>, instances: Vec<Instance>) -> Result<()> {
+        (**self).batch_deregister_instance(namespace_id, token, instances).await
+    }
+    async fn update_instance(&self, namespace_id: &str, token: Option<String>, instance: Instance) -> Result<()> {
+        (**self).update_instance(namespace_id, token, instance).await
+    }
+    async fn update_cluster(&self, namespace_id: &str, token: Option<String>, cluster: Cluster) -> Result<()> {
+        (**self).update_cluster(namespace_id, token, cluster).await
+    }
+    async fn query_instances(
+        &self, namespace_id: &str, token: Option<String>, service_name: String, clusters: &[&str], healthy_only: bool
+    ) -> Result<ServiceInfo> {
+        (**self).query_instances(namespace_id, token, service_name, clusters, healthy_only).await
+    }
+    async fn query_service(&self, namespace_id: &str, token: Option<String>, service_name: String) -> Result<Service> {
+        (**self).query_service(namespace_id, token, service_name).await
+    }
+    async fn query_all_service(
+        &self,
+        namespace_id: &str, token: Option<String>,
+        group_name: &str,
+        selector: Option<ExpressionSelector>,
+        page_num: u32, page_size: u32
+    ) -> Result<Vec<Service>> {
+        (**self).query_all_service(namespace_id, token, group_name, selector, page_num, page_size).await
+    }
+    async fn beat(&self, info: &BeatRequest) -> Result<BeatAck> {
+        (**self).beat(info).await
+    }
+    async fn subscribe(
+        &self, namespace_id: &str, token: Arc<dyn TokenSource>, service_name: &str, clusters: &[&str]
+    ) -> Result<()> {
+        (**self).subscribe(namespace_id, token, service_name, clusters).await
+    }
+    async fn unsubscribe(
+        &self, namespace_id: &str, token: Option<String>, service_name: &str, clusters: &[&str]
+    ) -> Result<()> {
+        (**self).unsubscribe(namespace_id, token, service_name, clusters).await
+    }
+    fn attach(&self, updater: ServiceUpdater) {
+        (**self).attach(updater)
+    }
+    fn close(&self) {
+        (**self).close()
+    }
+    async fn shutdown(&self) {
+        (**self).shutdown().await
+    }
+}
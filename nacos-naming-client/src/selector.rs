@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+
+use crate::{error::{Error, Result}, model::{Instance, ExpressionSelector}};
+
+
+const LABEL_SELECTOR_TYPE: &str = "label";
+const CONSUMER_PREFIX: &str = "CONSUMER.label.";
+const PROVIDER_PREFIX: &str = "PROVIDER.label.";
+
+/// condition on one metadata value of an instance
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    Eq(String),
+    In(Vec<String>),
+    /// also matches instances without the label
+    NotIn(Vec<String>),
+    Regex(Regex),
+    /// equals the given local label: `CONSUMER.label.{local} = PROVIDER.label.{key}`;
+    /// matches every instance if the local label is missing, like the nacos label selector
+    SameAsLocal(String)
+}
+
+impl Matcher {
+    fn matches(&self, value: Option<&String>, local_labels: &HashMap<String, String>) -> bool {
+        match self {
+            Matcher::Eq(expected) => value == Some(expected),
+            Matcher::In(values) => value.map(|value| values.contains(value)).unwrap_or(false),
+            Matcher::NotIn(values) => value.map(|value| !values.contains(value)).unwrap_or(true),
+            Matcher::Regex(regex) => value.map(|value| regex.is_match(value)).unwrap_or(false),
+            Matcher::SameAsLocal(local) => match local_labels.get(local) {
+                Some(expected) => value == Some(expected),
+                None => true
+            }
+        }
+    }
+}
+
+/// client side instance filter over instance metadata, evaluated against the local labels of the client
+#[derive(Debug, Clone, Default)]
+pub struct Selector {
+    filters: Vec<(String, Matcher)>,
+    /// label keys of the same zone routing, compared in order
+    preferred: Vec<String>
+}
+
+impl Selector {
+    pub fn new() -> Self {
+        Selector::default()
+    }
+
+    pub fn with(mut self, key: &str, matcher: Matcher) -> Self {
+        self.filters.push((key.to_string(), matcher));
+        self
+    }
+
+    pub fn eq(self, key: &str, value: &str) -> Self {
+        self.with(key, Matcher::Eq(value.to_string()))
+    }
+
+    pub fn is_in(self, key: &str, values: &[&str]) -> Self {
+        self.with(key, Matcher::In(values.iter().map(|value| value.to_string()).collect()))
+    }
+
+    pub fn not_in(self, key: &str, values: &[&str]) -> Self {
+        self.with(key, Matcher::NotIn(values.iter().map(|value| value.to_string()).collect()))
+    }
+
+    pub fn regex(self, key: &str, pattern: &str) -> Result<Self> {
+        let regex = Regex::new(pattern)
+            .map_err(|error| Error::InvalidSelector(format!("{}: {}", pattern, error)))?;
+        Ok(self.with(key, Matcher::Regex(regex)))
+    }
+
+    /// only instances whose label equals the local label of the same key
+    pub fn same_label(self, key: &str) -> Self {
+        self.with(key, Matcher::SameAsLocal(key.to_string()))
+    }
+
+    /// same zone first: keep the instances whose label equals the local label if there is any,
+    /// otherwise fall back to all instances; several keys narrow the preference in order
+    pub fn prefer_same_label(mut self, key: &str) -> Self {
+        self.preferred.push(key.to_string());
+        self
+    }
+
+    /// parse a nacos label expression, e.g. `CONSUMER.label.region = PROVIDER.label.region & ...`
+    pub fn parse_label_expression(expression: &str) -> Result<Self> {
+        let mut selector = Selector::new();
+        for condition in expression.split('&').map(str::trim).filter(|condition| !condition.is_empty()) {
+            let invalid = || Error::InvalidSelector(format!("invalid label condition: {}", condition));
+            let (left, right) = condition.split_once('=').ok_or_else(invalid)?;
+            let local = left.trim().strip_prefix(CONSUMER_PREFIX).ok_or_else(invalid)?;
+            let key = right.trim().strip_prefix(PROVIDER_PREFIX).ok_or_else(invalid)?;
+            if local.is_empty() || key.is_empty() {
+                return Err(invalid());
+            }
+            selector = selector.with(key, Matcher::SameAsLocal(local.to_string()));
+        }
+        Ok(selector)
+    }
+
+    /// a selector evaluating the server side selector on the client, only `label` selectors are supported
+    pub fn from_expression(selector: &ExpressionSelector) -> Result<Self> {
+        if selector.selector_type != LABEL_SELECTOR_TYPE {
+            return Err(Error::InvalidSelector(format!("unsupported selector type: {}", selector.selector_type)));
+        }
+        Self::parse_label_expression(selector.expression.as_str())
+    }
+
+    pub fn select(&self, local_labels: &HashMap<String, String>, hosts: Vec<Instance>) -> Vec<Instance> {
+        let mut hosts = hosts.into_iter()
+            .filter(|host| {
+                self.filters.iter().all(|(key, matcher)| matcher.matches(host.metadata.get(key), local_labels))
+            })
+            .collect::<Vec<_>>();
+        for key in self.preferred.iter() {
+            let local = match local_labels.get(key) {
+                Some(local) => local,
+                None => continue
+            };
+            if hosts.iter().any(|host| host.metadata.get(key) == Some(local)) {
+                hosts.retain(|host| host.metadata.get(key) == Some(local));
+            }
+        }
+        hosts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::model::Instance;
+
+    use super::Selector;
+
+    fn instance(ip: &str, labels: &[(&str, &str)]) -> Instance {
+        let mut instance = Instance::new_with_defaults("test", ip, 8080);
+        for (key, value) in labels {
+            instance.metadata.insert(key.to_string(), value.to_string());
+        }
+        instance
+    }
+
+    #[test]
+    fn test_select() {
+        let hosts = vec![
+            instance("127.0.0.1", &[("region", "sh"), ("zone", "a"), ("env", "prod")]),
+            instance("127.0.0.2", &[("region", "sh"), ("zone", "b"), ("env", "prod")]),
+            instance("127.0.0.3", &[("region", "bj"), ("zone", "a"), ("env", "gray")])
+        ];
+        let local = HashMap::from([
+            ("region".to_owned(), "sh".to_owned()), ("zone".to_owned(), "b".to_owned())
+        ]);
+
+        let selector = Selector::parse_label_expression("CONSUMER.label.region = PROVIDER.label.region").unwrap();
+        assert_eq!(selector.select(&local, hosts.clone()).len(), 2);
+        assert_eq!(selector.select(&HashMap::new(), hosts.clone()).len(), 3);
+        assert!(Selector::parse_label_expression("region = PROVIDER.label.region").is_err());
+
+        let selector = Selector::new().not_in("env", &["gray"]).regex("zone", "^[ab]$").unwrap();
+        assert_eq!(selector.select(&local, hosts.clone()).len(), 2);
+
+        let selector = Selector::new().is_in("env", &["prod", "gray"]).prefer_same_label("zone");
+        let selected = selector.select(&local, hosts.clone());
+        assert!(selected.len() == 1 && selected[0].ip == "127.0.0.2");
+        let selected = selector.select(&local, hosts[..1].to_vec());
+        assert!(selected.len() == 1 && selected[0].ip == "127.0.0.1");
+    }
+}
@@ -0,0 +1,195 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use tokio::{sync::Mutex, time::{Interval, MissedTickBehavior}};
+
+use crate::{
+    NamingClient, NamingRemote,
+    error::{Error, Result},
+    model::{Instance, get_same_instance},
+    util
+};
+
+
+/// calls made by one [InstanceSyncer::sync]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SyncReport {
+    pub registered: usize,
+    pub updated: usize,
+    pub deregistered: usize
+}
+
+/// instances of one service registered by the syncer, key: instance key
+type SyncedInstances = Arc<Mutex<HashMap<String, Instance>>>;
+
+#[derive(Debug, Default)]
+struct SyncDiff {
+    register: Vec<Instance>,
+    update: Vec<Instance>,
+    deregister: Vec<Instance>
+}
+
+/// mirrors desired instance lists into nacos: only the instances this syncer registered are compared,
+/// and only the needed register, update and deregister calls are made
+pub struct InstanceSyncer<R: NamingRemote + Clone + Send + 'static> {
+    client: Arc<NamingClient<R>>,
+    /// instances registered by the syncer, key: {group}@@{service}, then instance key;
+    /// each service has its own lock so a slow service doesn't block the others
+    registered: Mutex<HashMap<String, SyncedInstances>>,
+    limiter: Option<Mutex<Interval>>
+}
+
+impl<R: NamingRemote + Clone + Send + 'static> InstanceSyncer<R> {
+    /// `max_calls_per_second` of 0 disables rate limiting
+    pub fn new(client: Arc<NamingClient<R>>, max_calls_per_second: u32) -> Self {
+        let limiter = if max_calls_per_second == 0 {
+            None
+        } else {
+            // more than a billion calls per second round down to a zero period, which interval rejects
+            let period = (Duration::from_secs(1) / max_calls_per_second).max(Duration::from_nanos(1));
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            Some(Mutex::new(interval))
+        };
+        InstanceSyncer { client, registered: Mutex::new(HashMap::new()), limiter }
+    }
+
+    /// make the registered instances of the service equal to `desired`;
+    /// failed calls are reported as [Error::Batch] and retried by the next sync
+    pub async fn sync(&self, service_name: &str, group_name: &str, mut desired: Vec<Instance>) -> Result<SyncReport> {
+        let grouped_name = util::grouped_service_name(service_name, group_name);
+        for instance in desired.iter_mut() {
+            instance.service_name = grouped_name.clone();
+            instance.group_name = group_name.to_string();
+        }
+        let service = self.registered.lock().await
+            .entry(grouped_name.clone())
+            .or_default()
+            .clone();
+        // entries are changed right after each call succeeds, a cancelled sync keeps what it has done
+        let mut synced = service.lock().await;
+        let current = synced.values().cloned().collect::<Vec<_>>();
+        let diff = diff(&current, &desired);
+
+        let mut report = SyncReport::default();
+        let mut failed = vec![];
+        for instance in diff.deregister {
+            self.acquire().await;
+            match self.client.deregister_instance(instance.clone()).await {
+                Ok(_) => {
+                    synced.remove(instance.key().as_str());
+                    report.deregistered += 1;
+                },
+                Err(error) => failed.push((instance.key(), error))
+            }
+        }
+        for instance in diff.update {
+            self.acquire().await;
+            match self.client.update_instance(instance.clone()).await {
+                Ok(_) => {
+                    synced.insert(instance.key(), instance);
+                    report.updated += 1;
+                },
+                Err(error) => failed.push((instance.key(), error))
+            }
+        }
+        for instance in diff.register {
+            self.acquire().await;
+            match self.client.register_instance(instance.clone()).await {
+                Ok(_) => {
+                    synced.insert(instance.key(), instance);
+                    report.registered += 1;
+                },
+                Err(error) => failed.push((instance.key(), error))
+            }
+        }
+        if synced.is_empty() {
+            let mut registered = self.registered.lock().await;
+            // the map and this call hold the only references, no other sync waits on the service
+            if Arc::strong_count(&service) == 2 {
+                registered.remove(grouped_name.as_str());
+            }
+        }
+        drop(synced);
+        log::info!(
+            "[syncer] service[{}] registered: {}, updated: {}, deregistered: {}, failed: {}",
+            grouped_name, report.registered, report.updated, report.deregistered, failed.len()
+        );
+        if failed.is_empty() {
+            Ok(report)
+        } else {
+            Err(Error::Batch(failed))
+        }
+    }
+
+    /// deregister every instance registered by the syncer
+    pub async fn clear(&self) -> Result<()> {
+        let services = self.registered.lock().await.keys().cloned().collect::<Vec<_>>();
+        let mut failed = vec![];
+        for grouped_name in services {
+            let (group_name, service_name) = grouped_name.split_once("@@").expect("[syncer] never happen");
+            if let Err(error) = self.sync(service_name, group_name, vec![]).await {
+                match error {
+                    Error::Batch(errors) => failed.extend(errors),
+                    error => failed.push((grouped_name.clone(), error))
+                }
+            }
+        }
+        if failed.is_empty() { Ok(()) } else { Err(Error::Batch(failed)) }
+    }
+
+    async fn acquire(&self) {
+        if let Some(limiter) = self.limiter.as_ref() {
+            limiter.lock().await.tick().await;
+        }
+    }
+}
+
+fn diff(current: &[Instance], desired: &[Instance]) -> SyncDiff {
+    let mut ret = SyncDiff::default();
+    for instance in desired {
+        match get_same_instance(current, instance) {
+            None => ret.register.push(instance.clone()),
+            // the server can't move an instance to another cluster or change its ephemeral flag
+            Some(old) if old.cluster_name != instance.cluster_name || old.ephemeral != instance.ephemeral => {
+                ret.deregister.push(old.clone());
+                ret.register.push(instance.clone());
+            },
+            Some(old) if changed(old, instance) => ret.update.push(instance.clone()),
+            Some(_) => {}
+        }
+    }
+    for instance in current {
+        if get_same_instance(desired, instance).is_none() {
+            ret.deregister.push(instance.clone());
+        }
+    }
+    ret
+}
+
+fn changed(old: &Instance, new: &Instance) -> bool {
+    old.weight != new.weight || old.enabled != new.enabled
+        || old.healthy != new.healthy || old.metadata != new.metadata
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::Instance;
+
+    use super::diff;
+
+    #[test]
+    fn test_diff() {
+        let a = Instance::new_with_defaults("test", "127.0.0.1", 8080);
+        let b = Instance::new_with_defaults("test", "127.0.0.2", 8080);
+        let c = Instance::new_with_defaults("test", "127.0.0.3", 8080);
+        let mut moved = c.clone();
+        moved.cluster_name = "other".to_owned();
+        let mut heavier = b.clone();
+        heavier.weight = 2f64;
+
+        let ret = diff(&[a.clone(), b, c], &[heavier, moved, a]);
+        assert!(ret.register.len() == 1 && ret.register[0].cluster_name == "other");
+        assert!(ret.update.len() == 1 && ret.update[0].weight == 2f64);
+        assert!(ret.deregister.len() == 1 && ret.deregister[0].key() == "127.0.0.3:8080");
+    }
+}
@@ -0,0 +1,177 @@
+//! spans of the remote calls and background tasks, and W3C trace context propagation;
+//! everything here is a no-op unless the `tracing` feature is enabled
+use std::{future::Future, time::Duration};
+
+#[cfg(feature = "tracing")]
+use tracing::{Instrument, field::Empty};
+
+#[cfg(feature = "tracing")]
+pub(crate) type Traced<F> = tracing::instrument::Instrumented<F>;
+#[cfg(feature = "tracing")]
+pub(crate) type TracedCall<F> = tracing::instrument::Instrumented<tokio::task::futures::TaskLocalFuture<Option<TraceParent>, F>>;
+#[cfg(not(feature = "tracing"))]
+pub(crate) type TracedCall<F> = F;
+#[cfg(not(feature = "tracing"))]
+pub(crate) type Traced<F> = F;
+
+/// run a `NamingRemote` call in a span; the http client records server, status, latency and ids on it.
+/// inside a trace context the span gets its own `span_id`, which every attempt of the call sends as parent id
+pub(crate) fn remote_call<F: Future>(call: F, method: &'static str, namespace: &str, service: &str) -> TracedCall<F> {
+    #[cfg(feature = "tracing")]
+    {
+        let span = tracing::info_span!(
+            "nacos.remote", method, namespace, service, server = Empty, status = Empty, latency_ms = Empty,
+            request_id = Empty, trace_id = Empty, span_id = Empty, error = Empty
+        );
+        // a call made by another call, e.g. login, is a child of the outer call
+        let parent = CALL.try_with(Option::clone).ok().flatten()
+            .or_else(|| PARENT.try_with(TraceParent::clone).ok())
+            .map(|parent| parent.child());
+        if let Some(parent) = parent.as_ref() {
+            span.record("trace_id", parent.trace_id.as_str());
+            span.record("span_id", parent.parent_id.as_str());
+        }
+        CALL.scope(parent, call).instrument(span)
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (method, namespace, service);
+        call
+    }
+}
+
+/// run a background task in a span; `target` is the service or port the task works for
+pub(crate) fn background<F: Future>(task: F, name: &'static str, target: &str) -> Traced<F> {
+    #[cfg(feature = "tracing")]
+    {
+        task.instrument(tracing::info_span!("nacos.task", task = name, target))
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (name, target);
+        task
+    }
+}
+
+/// record the outcome of one http attempt on the current remote call span
+pub(crate) fn record_response(server: &str, status: Option<u16>, error: Option<&dyn std::fmt::Display>, latency: Duration) {
+    #[cfg(feature = "tracing")]
+    {
+        let span = tracing::Span::current();
+        span.record("server", server);
+        span.record("latency_ms", latency.as_millis() as u64);
+        if let Some(status) = status {
+            span.record("status", status);
+        }
+        if let Some(error) = error {
+            span.record("error", tracing::field::display(error));
+        }
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (server, status, error, latency);
+    }
+}
+
+/// the `traceparent` header for the next request, none outside a trace context;
+/// the request id is recorded on the current span
+#[cfg(feature = "tracing")]
+pub(crate) fn traceparent(request_id: &str) -> Option<String> {
+    tracing::Span::current().record("request_id", request_id);
+    CALL.try_with(|parent| parent.as_ref().map(TraceParent::to_string)).ok().flatten()
+}
+
+#[cfg(feature = "tracing")]
+pub use self::propagation::*;
+
+#[cfg(feature = "tracing")]
+mod propagation {
+    use std::{fmt::Display, future::Future};
+
+    pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+    tokio::task_local! {
+        /// the trace context of the caller
+        pub(super) static PARENT: TraceParent;
+        /// the trace context of the current remote call, its parent id is the id of the call span
+        pub(super) static CALL: Option<TraceParent>;
+    }
+
+    /// the `traceparent` header of the W3C trace context: `00-{trace_id}-{parent_id}-{flags}`
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct TraceParent {
+        /// 32 lowercase hex digits
+        pub trace_id: String,
+        /// 16 lowercase hex digits
+        pub parent_id: String,
+        pub flags: u8
+    }
+
+    impl TraceParent {
+        pub fn parse(header: &str) -> Option<Self> {
+            let mut parts = header.trim().split('-');
+            let (version, trace_id, parent_id, flags) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+            let hex = |value: &str, len: usize| {
+                value.len() == len && value.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+            };
+            if parts.next().is_some() || version != "00" || !hex(trace_id, 32) || !hex(parent_id, 16) || !hex(flags, 2) {
+                return None;
+            }
+            if trace_id.bytes().all(|b| b == b'0') || parent_id.bytes().all(|b| b == b'0') {
+                return None;
+            }
+            Some(TraceParent {
+                trace_id: trace_id.to_string(),
+                parent_id: parent_id.to_string(),
+                flags: u8::from_str_radix(flags, 16).ok()?
+            })
+        }
+
+        /// the same trace with a new parent id for a child span
+        pub(crate) fn child(&self) -> Self {
+            TraceParent {
+                trace_id: self.trace_id.clone(),
+                parent_id: format!("{:016x}", rand::random::<u64>().max(1)),
+                flags: self.flags
+            }
+        }
+    }
+
+    impl Display for TraceParent {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "00-{}-{}-{:02x}", self.trace_id, self.parent_id, self.flags)
+        }
+    }
+
+    /// nacos requests made by `fut` join the trace of `parent`, e.g. the traceparent of an incoming request;
+    /// requests outside such a scope, including the background beats and polls, send no traceparent
+    pub async fn with_traceparent<F: Future>(parent: TraceParent, fut: F) -> F::Output {
+        PARENT.scope(parent, fut).await
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use super::TraceParent;
+
+    #[tokio::test]
+    async fn test_traceparent() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let parent = TraceParent::parse(header).unwrap();
+        assert_eq!(parent.to_string(), header);
+        assert!(TraceParent::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none());
+        assert!(TraceParent::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7").is_none());
+
+        let attempts = || async { (super::traceparent("1"), super::traceparent("2")) };
+        let (first, second) = super::with_traceparent(
+            parent.clone(), async { super::remote_call(attempts(), "test", "", "").await }
+        ).await;
+        let next = TraceParent::parse(first.unwrap().as_str()).unwrap();
+        assert!(next.trace_id == parent.trace_id && next.parent_id != parent.parent_id && next.flags == parent.flags);
+        // retries of one call share the parent id of the call span
+        assert_eq!(second.unwrap(), next.to_string());
+
+        assert_eq!(super::remote_call(attempts(), "test", "", "").await, (None, None));
+        assert!(super::with_traceparent(parent, async { super::traceparent("id") }).await.is_none());
+    }
+}
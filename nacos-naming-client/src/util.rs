@@ -1,3 +1,74 @@
+use std::{future::Future, sync::{Arc, Mutex}};
+
+use tokio::{sync::watch, task::JoinHandle};
+
+
 pub fn grouped_service_name(service_name: &str, group_name: &str) -> String {
     format!("{}@@{}", group_name, service_name)
 }
+
+/// background tasks which are stopped by one signal and awaited together
+#[derive(Clone)]
+pub(crate) struct TaskGroup {
+    closed: Arc<watch::Sender<bool>>,
+    handles: Arc<Mutex<Vec<JoinHandle<()>>>>
+}
+
+impl TaskGroup {
+    pub fn new() -> Self {
+        let (tx, _) = watch::channel(false);
+        TaskGroup { closed: Arc::new(tx), handles: Arc::new(Mutex::new(vec![])) }
+    }
+
+    /// the task is dropped at its next await point once the group is closed
+    pub fn spawn<F: Future<Output = ()> + Send + 'static>(&self, task: F) {
+        let mut closed = self.closed.subscribe();
+        if *closed.borrow() {
+            return;
+        }
+        let handle = tokio::spawn(async move {
+            tokio::select! {
+                _ = task => {},
+                _ = closed.wait_for(|closed| *closed) => {}
+            }
+        });
+        let mut handles = self.handles.lock().expect("[task_group] lock poisoned");
+        handles.retain(|handle| !handle.is_finished());
+        handles.push(handle);
+    }
+
+    pub fn close(&self) {
+        self.closed.send_replace(true);
+    }
+
+    /// wait for every task spawned so far
+    pub async fn join(&self) {
+        let handles = std::mem::take(&mut *self.handles.lock().expect("[task_group] lock poisoned"));
+        for handle in handles {
+            if let Err(error) = handle.await {
+                if error.is_panic() {
+                    log::error!("background task panicked: {}", error);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::TaskGroup;
+
+    #[tokio::test]
+    async fn test_task_group() {
+        let group = TaskGroup::new();
+        group.spawn(async { tokio::time::sleep(Duration::from_secs(3600)).await });
+        group.spawn(async {});
+        group.close();
+        tokio::time::timeout(Duration::from_secs(1), group.join()).await.unwrap();
+
+        group.spawn(async { panic!("spawned after close") });
+        assert!(group.handles.lock().unwrap().is_empty());
+    }
+}
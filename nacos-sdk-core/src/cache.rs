@@ -1,16 +1,27 @@
-use std::{path::Path, collections::HashMap};
+use std::{path::Path, collections::HashMap, sync::atomic::{AtomicU64, Ordering}};
 
 use serde::{Serialize, de::DeserializeOwned};
-use tokio::fs;
+use tokio::{fs, io::AsyncWriteExt};
 
 use crate::error::*;
 
+/// version of the cache file format written by [write_file]
+pub const CACHE_VERSION: u32 = 1;
+/// first line of a versioned cache file; java clients skip lines that do not start with `{`
+const VERSION_HEADER: &str = "#nacos-cache-version:";
+/// corrupt cache files are moved into this sub dir instead of blocking the whole dir
+pub const QUARANTINE_DIR: &str = ".quarantine";
+const TMP_SUFFIX: &str = ".tmp";
+/// tells apart the temp files of concurrent writes in one process, e.g. a push and a poll of one service
+static TMP_SEQ: AtomicU64 = AtomicU64::new(0);
+
 
 pub async fn write_file<T: Serialize, D: AsRef<Path>>(content: &T, dir: D, file_name: &str) -> Result<()> {
-    let contents = serde_json::to_string(content)?;
+    let contents = format!("{}{}\n{}", VERSION_HEADER, CACHE_VERSION, serde_json::to_string(content)?);
     write_file_str(contents.as_str(), dir, file_name).await
 }
 
+/// write to a temp file then rename it, so a crash never leaves a truncated cache file behind
 pub async fn write_file_str<D: AsRef<Path>>(contents: &str, dir: D, file_name: &str) -> Result<()> {
     let dir = dir.as_ref();
     if !dir.exists() {
@@ -18,36 +29,87 @@ pub async fn write_file_str<D: AsRef<Path>>(contents: &str, dir: D, file_name: &
             .map_err(|err| Error::Fs("failed to create cache dir".to_owned(), err))?;
     }
     let path = dir.join(file_name);
-    fs::write(path, contents).await
-        .map_err(|err| Error::Fs("can not write cache file".to_owned(), err))?;
-    Ok(())
+    let tmp_path = dir.join(format!(
+        ".{}.{}.{}{}", file_name, std::process::id(), TMP_SEQ.fetch_add(1, Ordering::Relaxed), TMP_SUFFIX
+    ));
+    let mut file = fs::File::create(&tmp_path).await
+        .map_err(|err| Error::Fs("can not create cache file".to_owned(), err))?;
+    let written = async {
+        file.write_all(contents.as_bytes()).await?;
+        file.sync_all().await
+    }.await;
+    if let Err(err) = written {
+        let _ = fs::remove_file(&tmp_path).await;
+        return Err(Error::Fs("can not write cache file".to_owned(), err));
+    }
+    fs::rename(&tmp_path, path).await
+        .map_err(|err| Error::Fs("can not replace cache file".to_owned(), err))
 }
 
+/// read every cache file in the dir; files that can not be parsed are skipped and quarantined
 pub async fn read_dir<T: DeserializeOwned>(dir: impl AsRef<Path>) -> Result<HashMap<String, T>> {
+    parse_dir(dir.as_ref(), |_| true, true).await
+}
+
+/// read the files whose name matches the filter without touching the dir, files that can not be parsed
+/// are only logged; for dirs maintained by others, e.g. the failover dir
+pub async fn read_dir_filter<T: DeserializeOwned>(
+    dir: impl AsRef<Path>,
+    filter: impl Fn(&str) -> bool
+) -> Result<HashMap<String, T>> {
+    parse_dir(dir.as_ref(), filter, false).await
+}
+
+async fn parse_dir<T: DeserializeOwned>(
+    dir: &Path,
+    filter: impl Fn(&str) -> bool,
+    quarantine_corrupt: bool
+) -> Result<HashMap<String, T>> {
     let content = read_dir_str(dir).await?;
     let mut ret = HashMap::new();
-    for (key, value) in content.into_iter() {
-        let _ = match serde_json::from_str::<T>(value.as_str()) {
-            Ok(x) => ret.insert(key, x),
-            Err(err) => {
-                log::error!("failed to parse cache file[{}]: {}", key, err);
-                return Err(Error::Serde(err));
-            }
+    for (key, value) in content.into_iter().filter(|(key, _)| filter(key.as_str())) {
+        match parse_content::<T>(value.as_str()) {
+            Ok(x) => {
+                ret.insert(key, x);
+            },
+            Err(err) if quarantine_corrupt => {
+                log::error!("failed to parse cache file[{}], quarantine it: {}", key, err);
+                quarantine(dir, key.as_str()).await;
+            },
+            Err(err) => log::error!("failed to parse file[{}], skip it: {}", dir.join(key).display(), err)
         };
     }
     Ok(ret)
 }
 
+/// read every regular file in the dir; hidden files, sub dirs and unreadable files are skipped
 pub async fn read_dir_str<D: AsRef<Path>>(dir: D) -> Result<HashMap<String, String>> {
     let mut dir = fs::read_dir(dir.as_ref()).await
         .map_err(|err|Error::Fs("failed to read cache dir".to_owned(), err))?;
-    
+
     let mut ret_val = HashMap::new();
-    while let Some(entry) = dir.next_entry().await.expect("") {
-        let file_path = entry.path();
-        let content = read_file_str(file_path).await?;
-        let file_name = entry.file_name().into_string().expect("illegal charactor in filename");
-        ret_val.insert(file_name, content);
+    while let Some(entry) = dir.next_entry().await
+        .map_err(|err| Error::Fs("failed to read cache dir entry".to_owned(), err))? {
+        let file_name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(name) => {
+                log::warn!("skip cache file with illegal charactor in filename: {:?}", name);
+                continue
+            }
+        };
+        if file_name.starts_with('.') {
+            continue
+        }
+        match entry.file_type().await {
+            Ok(file_type) if file_type.is_file() => {},
+            _ => continue
+        }
+        match read_file_str(entry.path()).await {
+            Ok(content) => {
+                ret_val.insert(file_name, content);
+            },
+            Err(err) => log::warn!("skip unreadable cache file: {}", err)
+        }
     }
 
     Ok(ret_val)
@@ -55,12 +117,137 @@ pub async fn read_dir_str<D: AsRef<Path>>(dir: D) -> Result<HashMap<String, Stri
 
 pub async fn read_file<T: DeserializeOwned>(file: impl AsRef<Path>) -> Result<T> {
     let content = read_file_str(file).await?;
-    let ret = serde_json::from_str(content.as_str())?;
-    Ok(ret)
+    parse_content(content.as_str())
 }
 
 pub async fn read_file_str<D: AsRef<Path>>(file: D) -> Result<String> {
     let file = file.as_ref();
     fs::read_to_string(file).await
         .map_err(|err| Error::Fs(format!("failed to read file: {:?}", file), err))
-}
\ No newline at end of file
+}
+
+/// files without a version header are legacy plain json files
+fn parse_content<T: DeserializeOwned>(content: &str) -> Result<T> {
+    let body = match content.strip_prefix(VERSION_HEADER) {
+        None => content,
+        Some(rest) => {
+            let (version, body) = rest.split_once('\n').unwrap_or((rest, ""));
+            let version = version.trim();
+            if version.parse::<u32>().ok() != Some(CACHE_VERSION) {
+                return Err(Error::UnsupportedVersion(version.to_owned()));
+            }
+            body
+        }
+    };
+    Ok(serde_json::from_str(body)?)
+}
+
+/// encode a cache key to a file name the same way java's `URLEncoder` does,
+/// so failover dirs can be shared with java clients; a leading `.` is escaped to avoid hidden files
+pub fn encode_file_name(key: &str) -> String {
+    let mut ret = String::with_capacity(key.len());
+    for (i, byte) in key.bytes().enumerate() {
+        match byte {
+            b'.' if i == 0 => ret.push_str("%2E"),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'-' | b'*' | b'_' => ret.push(byte as char),
+            b' ' => ret.push('+'),
+            _ => ret.push_str(format!("%{:02X}", byte).as_str())
+        }
+    }
+    ret
+}
+
+async fn quarantine(dir: &Path, file_name: &str) {
+    let quarantine_dir = dir.join(QUARANTINE_DIR);
+    let moved = async {
+        fs::create_dir_all(&quarantine_dir).await?;
+        fs::rename(dir.join(file_name), quarantine_dir.join(file_name)).await
+    }.await;
+    if let Err(err) = moved {
+        log::warn!("failed to quarantine cache file[{}]: {}", file_name, err);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nacos-cache-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_write_and_read() -> Result<()> {
+        let dir = temp_dir("rw");
+        let mut content = HashMap::new();
+        content.insert("key".to_owned(), 1u32);
+        write_file(&content, &dir, "versioned").await?;
+        write_file_str("{\"key\":2}", &dir, "legacy").await?;
+
+        let map: HashMap<String, HashMap<String, u32>> = read_dir(&dir).await?;
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["versioned"]["key"], 1);
+        assert_eq!(map["legacy"]["key"], 2);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2, "no temp file left behind");
+
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_write() -> Result<()> {
+        let dir = temp_dir("concurrent");
+        let contents = (0..32u32).map(|i| vec![i; 4096]).collect::<Vec<_>>();
+        let writes = contents.iter().cloned().map(|content| {
+            let dir = dir.clone();
+            tokio::spawn(async move { write_file(&content, dir, "key").await })
+        }).collect::<Vec<_>>();
+        for write in writes {
+            write.await.expect("write task panicked")?;
+        }
+
+        let written: Vec<u32> = read_file(dir.join("key")).await?;
+        assert!(contents.contains(&written), "one of the writes wins as a whole");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1, "no temp file left behind");
+
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_quarantine_corrupt_file() -> Result<()> {
+        let dir = temp_dir("corrupt");
+        write_file(&1u32, &dir, "good").await?;
+        write_file_str("{\"truncat", &dir, "truncated").await?;
+        write_file_str("#nacos-cache-version:99\n1", &dir, "future").await?;
+
+        let map: HashMap<String, u32> = read_dir(&dir).await?;
+        assert_eq!(map.len(), 1);
+        assert_eq!(map["good"], 1);
+        assert!(dir.join(QUARANTINE_DIR).join("truncated").exists());
+        assert!(dir.join(QUARANTINE_DIR).join("future").exists());
+
+        let map: HashMap<String, u32> = read_dir(&dir).await?;
+        assert_eq!(map.len(), 1, "quarantine dir is skipped");
+
+        write_file_str("{\"truncat", &dir, "manual").await?;
+        let map: HashMap<String, u32> = read_dir_filter(&dir, |name| name != "good").await?;
+        assert!(map.is_empty());
+        assert!(dir.join("manual").exists(), "filtered reads never move files");
+
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_name_codec() {
+        assert_eq!(encode_file_name("DEFAULT_GROUP@@svc"), "DEFAULT_GROUP%40%40svc");
+        assert_eq!(encode_file_name("a b/c中"), "a+b%2Fc%E4%B8%AD");
+        assert_eq!(encode_file_name(".."), "%2E.");
+        assert_eq!(encode_file_name("../../etc/passwd"), "%2E.%2F..%2Fetc%2Fpasswd");
+    }
+}
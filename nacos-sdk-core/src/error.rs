@@ -8,6 +8,9 @@ pub enum Error {
     #[error(transparent)]
     Serde(#[from] serde_json::Error),
 
+    #[error("unsupported cache version: {0}")]
+    UnsupportedVersion(String),
+
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
\ No newline at end of file
@@ -0,0 +1,130 @@
+use std::{collections::HashSet, sync::Arc};
+
+use crate::ChannelOptions;
+
+use async_trait::async_trait;
+use nacos_naming_client::{ServiceChangeListener, model::Instance};
+use tokio::sync::{mpsc, watch};
+use tonic::transport::Endpoint;
+use tower::discover::Change;
+
+
+/// keeps only the latest instance snapshot of a service;
+/// a slow consumer skips intermediate snapshots but never misses the final state
+pub(crate) struct SnapshotListener {
+    tx: Arc<watch::Sender<Option<Vec<Instance>>>>
+}
+
+impl SnapshotListener {
+    pub fn new() -> (Self, watch::Receiver<Option<Vec<Instance>>>) {
+        let (tx, rx) = watch::channel(None);
+        (SnapshotListener { tx: Arc::new(tx) }, rx)
+    }
+
+    pub fn sender(&self) -> Arc<watch::Sender<Option<Vec<Instance>>>> {
+        self.tx.clone()
+    }
+}
+
+#[async_trait]
+impl ServiceChangeListener for SnapshotListener {
+    async fn changed(&self, service_name: &str, hosts: Vec<Instance>) {
+        log::debug!("obtain service[{}] change from nacos: {:?}", service_name, hosts);
+        self.tx.send_replace(Some(hosts));
+    }
+}
+
+/// seed the snapshot with the cached instances unless a change has already arrived
+pub(crate) fn seed(tx: &watch::Sender<Option<Vec<Instance>>>, hosts: Vec<Instance>) {
+    tx.send_if_modified(|cur| {
+        if cur.is_some() {
+            return false;
+        }
+        *cur = Some(hosts);
+        true
+    });
+}
+
+/// diff every snapshot against the endpoints already in the balance channel and send the changes;
+/// ends when the channel or the subscription is dropped
+pub(crate) async fn sync_endpoints(
+    service_name: String,
+    mut snapshots: watch::Receiver<Option<Vec<Instance>>>,
+    balance: mpsc::Sender<Change<String, Endpoint>>,
+    options: ChannelOptions
+) {
+    let mut current = HashSet::new();
+    loop {
+        let hosts = snapshots.borrow_and_update().clone().unwrap_or_default();
+        let mut endpoints = options.build_endpoints(hosts);
+
+        let removed = current.iter()
+            .filter(|key| !endpoints.contains_key(*key))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in removed {
+            log::info!("service[{}] remove endpoint: {}", service_name, key);
+            current.remove(&key);
+            if balance.send(Change::Remove(key)).await.is_err() {
+                return;
+            }
+        }
+        let inserted = endpoints.keys()
+            .filter(|key| !current.contains(*key))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in inserted {
+            let endpoint = endpoints.remove(&key).expect("[discover] never happen");
+            log::info!("service[{}] insert endpoint: {}", service_name, key);
+            current.insert(key.clone());
+            if balance.send(Change::Insert(key, endpoint)).await.is_err() {
+                return;
+            }
+        }
+
+        tokio::select! {
+            changed = snapshots.changed() => if changed.is_err() { return },
+            _ = balance.closed() => return
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nacos_naming_client::{ServiceChangeListener, model::Instance};
+    use tokio::sync::mpsc;
+    use tower::discover::Change;
+
+    use crate::ChannelOptions;
+
+    use super::{SnapshotListener, sync_endpoints};
+
+    #[tokio::test]
+    async fn test_sync_latest_snapshot() {
+        let (listener, snapshots) = SnapshotListener::new();
+        let (tx, mut rx) = mpsc::channel(1);
+        let task = tokio::spawn(sync_endpoints("test".to_owned(), snapshots, tx, ChannelOptions::default()));
+
+        let a = Instance::new_with_defaults("test", "127.0.0.1", 8080);
+        let b = Instance::new_with_defaults("test", "127.0.0.2", 8080);
+        listener.changed("test", vec![a.clone()]).await;
+        match rx.recv().await.unwrap() {
+            Change::Insert(key, _) => assert_eq!(key, "http://127.0.0.1:8080"),
+            Change::Remove(key) => panic!("unexpected remove: {}", key)
+        }
+
+        // intermediate snapshots may be skipped, the final state must arrive
+        listener.changed("test", vec![a.clone(), b.clone()]).await;
+        listener.changed("test", vec![b]).await;
+        let mut changes = vec![];
+        while changes.len() < 2 {
+            changes.push(rx.recv().await.unwrap());
+        }
+        assert!(matches!(&changes[0], Change::Remove(key) if key == "http://127.0.0.1:8080"));
+        assert!(matches!(&changes[1], Change::Insert(key, _) if key == "http://127.0.0.2:8080"));
+
+        drop(rx);
+        listener.changed("test", vec![a]).await;
+        task.await.unwrap();
+    }
+}
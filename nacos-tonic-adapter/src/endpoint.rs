@@ -0,0 +1,174 @@
+use std::{collections::HashMap, time::Duration};
+
+use nacos_naming_client::model::Instance;
+#[cfg(feature = "tls")]
+use tonic::transport::ClientTlsConfig;
+use tonic::transport::Endpoint;
+
+
+/// instance metadata holding the grpc port if it differs from the registered port
+pub const METADATA_GRPC_PORT: &str = "gRPC_port";
+/// instance metadata `secure=true` switches the endpoint to https
+pub const METADATA_SECURE: &str = "secure";
+/// instance metadata holding the endpoint scheme, `http` or `https`
+pub const METADATA_SCHEME: &str = "scheme";
+/// upper bound of balance channel entries per instance, see [ChannelOptions::weighted]
+pub const MAX_WEIGHT_REPLICAS: usize = 16;
+
+/// settings applied to every endpoint of a channel
+#[derive(Clone, Default)]
+pub struct ChannelOptions {
+    pub timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    pub concurrency_limit: Option<usize>,
+    pub tcp_keepalive: Option<Duration>,
+    pub http2_keep_alive_interval: Option<Duration>,
+    pub keep_alive_timeout: Option<Duration>,
+    pub initial_stream_window_size: Option<u32>,
+    pub initial_connection_window_size: Option<u32>,
+    /// tls settings of https endpoints, with the ca certificate and the domain name the servers present;
+    /// https endpoints are skipped if none
+    #[cfg(feature = "tls")]
+    pub tls: Option<ClientTlsConfig>,
+    /// spread requests by instance weight: an instance is added to the balance channel
+    /// `weight / min_weight` times, at most [MAX_WEIGHT_REPLICAS]
+    pub weighted: bool
+}
+
+impl ChannelOptions {
+    fn apply(&self, mut endpoint: Endpoint, secure: bool) -> Option<Endpoint> {
+        if let Some(timeout) = self.timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+        if let Some(limit) = self.concurrency_limit {
+            endpoint = endpoint.concurrency_limit(limit);
+        }
+        if self.tcp_keepalive.is_some() {
+            endpoint = endpoint.tcp_keepalive(self.tcp_keepalive);
+        }
+        if let Some(interval) = self.http2_keep_alive_interval {
+            endpoint = endpoint.http2_keep_alive_interval(interval);
+        }
+        if let Some(timeout) = self.keep_alive_timeout {
+            endpoint = endpoint.keep_alive_timeout(timeout);
+        }
+        endpoint = endpoint
+            .initial_stream_window_size(self.initial_stream_window_size)
+            .initial_connection_window_size(self.initial_connection_window_size);
+        if secure {
+            endpoint = self.apply_tls(endpoint)?;
+        }
+        Some(endpoint)
+    }
+
+    #[cfg(feature = "tls")]
+    fn apply_tls(&self, endpoint: Endpoint) -> Option<Endpoint> {
+        // the tonic default has neither roots nor a domain, no server could be verified with it
+        let tls = match self.tls.clone() {
+            Some(tls) => tls,
+            None => {
+                log::error!("secure endpoint[{}] requires the tls config of the channel options", endpoint.uri());
+                return None;
+            }
+        };
+        match endpoint.tls_config(tls) {
+            Ok(endpoint) => Some(endpoint),
+            Err(error) => {
+                log::error!("invalid tls config: {}", error);
+                None
+            }
+        }
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn apply_tls(&self, endpoint: Endpoint) -> Option<Endpoint> {
+        log::error!("secure endpoint[{}] requires the tls feature", endpoint.uri());
+        None
+    }
+
+    /// endpoints of the healthy instances keyed by url; weighted replicas get a `#n` suffix
+    pub(crate) fn build_endpoints(&self, hosts: Vec<Instance>) -> HashMap<String, Endpoint> {
+        let hosts = hosts.into_iter()
+            .filter(|instance| instance.healthy && instance.enabled && instance.weight > 0f64)
+            .collect::<Vec<_>>();
+        let min_weight = hosts.iter().map(|instance| instance.weight).fold(f64::MAX, f64::min);
+
+        let mut ret = HashMap::new();
+        for instance in hosts {
+            let replicas = if self.weighted {
+                ((instance.weight / min_weight).round() as usize).clamp(1, MAX_WEIGHT_REPLICAS)
+            } else {
+                1
+            };
+            let (url, secure) = endpoint_url(&instance);
+            let endpoint = match Endpoint::from_shared(url.clone()) {
+                Ok(endpoint) => endpoint,
+                Err(error) => {
+                    log::error!("invalid url: {}", error);
+                    continue
+                }
+            };
+            let endpoint = match self.apply(endpoint, secure) {
+                Some(endpoint) => endpoint,
+                None => continue
+            };
+            for i in 1..replicas {
+                ret.insert(format!("{}#{}", url, i), endpoint.clone());
+            }
+            ret.insert(url, endpoint);
+        }
+        ret
+    }
+}
+
+fn endpoint_url(instance: &Instance) -> (String, bool) {
+    let port = instance.metadata.get(METADATA_GRPC_PORT).cloned().unwrap_or(instance.port.to_string());
+    let secure = instance.metadata.get(METADATA_SECURE).map(|secure| secure == "true").unwrap_or(false)
+        || instance.metadata.get(METADATA_SCHEME).map(|scheme| scheme == "https").unwrap_or(false);
+    let scheme = if secure { "https" } else { "http" };
+    let host = if instance.ip.contains(':') {
+        format!("[{}]", instance.ip)
+    } else {
+        instance.ip.clone()
+    };
+    (format!("{}://{}:{}", scheme, host, port), secure)
+}
+
+#[cfg(test)]
+mod test {
+    use nacos_naming_client::model::Instance;
+
+    use super::{ChannelOptions, METADATA_SCHEME};
+
+    #[test]
+    fn test_build_endpoints() {
+        let mut light = Instance::new_with_defaults("test", "127.0.0.1", 8080);
+        light.weight = 0.5;
+        let mut heavy = Instance::new_with_defaults("test", "127.0.0.2", 8080);
+        heavy.weight = 1.5;
+        heavy.metadata.insert(METADATA_SCHEME.to_owned(), "https".to_owned());
+
+        let options = ChannelOptions::default();
+        let endpoints = options.build_endpoints(vec![light.clone(), heavy.clone()]);
+        assert_eq!(endpoints.len(), 1);
+        assert!(endpoints.contains_key("http://127.0.0.1:8080"));
+        #[cfg(feature = "tls")]
+        {
+            let tls = tonic::transport::ClientTlsConfig::new().domain_name("nacos.test");
+            let options = ChannelOptions { tls: Some(tls), ..Default::default() };
+            let endpoints = options.build_endpoints(vec![light.clone(), heavy.clone()]);
+            assert!(endpoints.contains_key("https://127.0.0.2:8080"));
+        }
+
+        let options = ChannelOptions { weighted: true, ..Default::default() };
+        light.metadata.insert(METADATA_SCHEME.to_owned(), "http".to_owned());
+        heavy.metadata.remove(METADATA_SCHEME);
+        heavy.ip = "::1".to_owned();
+        let endpoints = options.build_endpoints(vec![light, heavy]);
+        assert_eq!(endpoints.len(), 4);
+        assert!(endpoints.contains_key("http://[::1]:8080#2"));
+    }
+}
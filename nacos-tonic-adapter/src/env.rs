@@ -0,0 +1,21 @@
+use nacos_naming_client::{NamingClient, HttpNamingRemote, NamingConfig, error::Result};
+
+use crate::TonicAdapter;
+
+
+/// build a naming config from `NACOS_*` environment variables, see [NamingConfig::builder]
+pub fn config_from_env() -> Result<NamingConfig> {
+    Ok(NamingConfig::builder().env_overlay().build()?)
+}
+
+/// build a http naming client from `NACOS_*` environment variables
+pub async fn client_from_env() -> Result<NamingClient<HttpNamingRemote>> {
+    NamingClient::new_http(config_from_env()?).await
+}
+
+impl TonicAdapter<HttpNamingRemote> {
+    /// build an adapter over a http naming client configured by `NACOS_*` environment variables
+    pub async fn from_env() -> Result<Self> {
+        Ok(TonicAdapter::new(std::sync::Arc::new(client_from_env().await?)))
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+use nacos_naming_client::{NamingClient, NamingRemote, model::Instance, error::Result};
+use tokio::sync::{Mutex, watch};
+
+#[cfg(feature = "health")]
+use tonic_health::{ServingStatus, server::HealthReporter};
+
+
+/// how a not serving instance is reflected into nacos
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HealthAction {
+    /// set `enabled=false`, consumers stop picking the instance
+    Disable,
+    /// set `healthy=false`; only sticks for persistent instances,
+    /// the next beat of an ephemeral instance marks it healthy again
+    Unhealthy,
+    /// set the weight to 0 so the instance drains without being disabled
+    Drain
+}
+
+/// reflects the serving status of a registered grpc server into its nacos instance
+pub struct HealthBridge<R: NamingRemote + Clone + Send + 'static> {
+    client: Arc<NamingClient<R>>,
+    instance: Instance,
+    action: HealthAction,
+    /// weight before [HealthAction::Drain] set it to 0
+    drained_weight: Arc<Mutex<Option<f64>>>
+}
+
+impl<R: NamingRemote + Clone + Send + 'static> Clone for HealthBridge<R> {
+    fn clone(&self) -> Self {
+        HealthBridge {
+            client: self.client.clone(),
+            instance: self.instance.clone(),
+            action: self.action,
+            drained_weight: self.drained_weight.clone()
+        }
+    }
+}
+
+impl<R: NamingRemote + Clone + Send + 'static> HealthBridge<R> {
+    pub(crate) fn new(client: Arc<NamingClient<R>>, instance: Instance, action: HealthAction) -> Self {
+        HealthBridge { client, instance, action, drained_weight: Arc::new(Mutex::new(None)) }
+    }
+
+    /// update the instance in nacos if the serving status changed; only the field of the action is changed,
+    /// weight and metadata updated by others since the registration are kept
+    pub async fn set_serving(&self, serving: bool) -> Result<()> {
+        // a watch task and a reporter may share the bridge, a recovery must not be overtaken by the outage it reverts
+        let mut drained_weight = self.drained_weight.lock().await;
+        let current = self.client.registered_instance(&self.instance).await
+            .unwrap_or_else(|| self.instance.clone());
+        let target = match apply_action(&current, self.action, serving, *drained_weight) {
+            Some(target) => target,
+            None => return Ok(())
+        };
+        log::info!("instance[{}] serving: {}, update nacos", self.instance.key(), serving);
+        self.client.update_instance(target).await?;
+        if self.action == HealthAction::Drain {
+            *drained_weight = if serving { None } else { Some(current.weight) };
+        }
+        Ok(())
+    }
+
+    /// follow the serving status published on the receiver until its sender is dropped
+    pub fn watch(self, mut serving: watch::Receiver<bool>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let value = *serving.borrow_and_update();
+                if let Err(error) = self.set_serving(value).await {
+                    log::error!("failed to update serving status of instance[{}]: {}", self.instance.key(), error);
+                }
+                if serving.changed().await.is_err() {
+                    return;
+                }
+            }
+        })
+    }
+
+    /// wrap a tonic-health reporter; status changes of `service_name` are also sent to nacos,
+    /// use `""` for the overall server status
+    #[cfg(feature = "health")]
+    pub fn reporter(self, reporter: HealthReporter, service_name: &str) -> BridgedReporter<R> {
+        BridgedReporter { reporter, service_name: service_name.to_string(), bridge: self }
+    }
+}
+
+/// `instance` with the field of `action` set for `serving`, none if it is set already
+fn apply_action(instance: &Instance, action: HealthAction, serving: bool, drained_weight: Option<f64>) -> Option<Instance> {
+    let mut target = instance.clone();
+    match (action, drained_weight) {
+        (HealthAction::Disable, _) => target.enabled = serving,
+        (HealthAction::Unhealthy, _) => target.healthy = serving,
+        (HealthAction::Drain, Some(weight)) if serving => target.weight = weight,
+        (HealthAction::Drain, None) if !serving => target.weight = 0f64,
+        // never drained, or drained already
+        (HealthAction::Drain, _) => return None
+    }
+    let changed = target.enabled != instance.enabled || target.healthy != instance.healthy
+        || target.weight != instance.weight;
+    changed.then_some(target)
+}
+
+/// a tonic-health reporter that forwards the status of one service to nacos
+#[cfg(feature = "health")]
+pub struct BridgedReporter<R: NamingRemote + Clone + Send + 'static> {
+    reporter: HealthReporter,
+    service_name: String,
+    bridge: HealthBridge<R>
+}
+
+#[cfg(feature = "health")]
+impl<R: NamingRemote + Clone + Send + 'static> BridgedReporter<R> {
+    pub async fn set_serving<S: tonic::server::NamedService>(&mut self) {
+        self.set_service_status(S::NAME, ServingStatus::Serving).await
+    }
+
+    pub async fn set_not_serving<S: tonic::server::NamedService>(&mut self) {
+        self.set_service_status(S::NAME, ServingStatus::NotServing).await
+    }
+
+    pub async fn set_service_status(&mut self, service_name: &str, status: ServingStatus) {
+        self.reporter.set_service_status(service_name, status).await;
+        if service_name != self.service_name {
+            return;
+        }
+        if let Err(error) = self.bridge.set_serving(status == ServingStatus::Serving).await {
+            log::error!("failed to report status of service[{}] to nacos: {}", service_name, error);
+        }
+    }
+
+    pub fn inner(&self) -> &HealthReporter {
+        &self.reporter
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nacos_naming_client::model::Instance;
+
+    use super::{HealthAction, apply_action};
+
+    #[test]
+    fn test_apply_action() {
+        let instance = Instance::new_with_defaults("test", "127.0.0.1", 8080);
+        assert!(apply_action(&instance, HealthAction::Disable, true, None).is_none());
+        assert!(apply_action(&instance, HealthAction::Drain, true, None).is_none());
+
+        let disabled = apply_action(&instance, HealthAction::Disable, false, None).unwrap();
+        assert!(!disabled.enabled);
+        // a weight changed while disabled is kept on recovery
+        let mut reweighted = disabled.clone();
+        reweighted.weight = 5f64;
+        let recovered = apply_action(&reweighted, HealthAction::Disable, true, None).unwrap();
+        assert!(recovered.enabled && recovered.weight == 5f64);
+        assert!(!apply_action(&instance, HealthAction::Unhealthy, false, None).unwrap().healthy);
+
+        let drained = apply_action(&instance, HealthAction::Drain, false, None).unwrap();
+        assert!(drained.enabled && drained.weight == 0f64);
+        assert!(apply_action(&drained, HealthAction::Drain, false, Some(instance.weight)).is_none());
+        let restored = apply_action(&drained, HealthAction::Drain, true, Some(instance.weight)).unwrap();
+        assert_eq!(restored.weight, instance.weight);
+    }
+}
@@ -1,161 +1,93 @@
-use std::{sync::Arc, collections::HashSet};
+use std::{collections::HashMap, sync::Arc};
 
-use crossbeam::queue::SegQueue;
 use nacos_naming_client:: {
-    NamingClient, HttpNamingRemote, NamingConfig, constants, ServerConfig,
-    ServiceChangeListener, model::Instance,
+    NamingClient, NamingRemote, model::Instance,
     error::Result
 };
 
-use async_once::AsyncOnce;
-use lazy_static::lazy_static;
-use async_trait::async_trait;
-use tokio::sync::Mutex;
-use tower::discover::Change;
-use tonic::transport::{Endpoint, Channel};
-use log;
+use tonic::transport::Channel;
 
-pub use nacos_naming_client::error;
-
-lazy_static! {
-    pub static ref NACOS_CLIENT: AsyncOnce<NamingClient<HttpNamingRemote>> = AsyncOnce::new(async {
-        gen_client_from_env().await
-    });
-}
+#[cfg(feature = "env")]
+mod env;
+mod discover;
+mod endpoint;
+mod registration;
+mod health;
 
+pub use nacos_naming_client::error;
+#[cfg(feature = "env")]
+pub use env::{config_from_env, client_from_env};
+pub use registration::{Registration, DEFAULT_DRAIN_PERIOD};
+pub use endpoint::*;
+pub use health::{HealthAction, HealthBridge};
+#[cfg(feature = "health")]
+pub use health::BridgedReporter;
 
-async fn gen_client_from_env() -> NamingClient<HttpNamingRemote> {
-    use std::env::var;
-    let namespace = var("NACOS_NAMESPACE").unwrap_or(constants::DEFAULT_NAMSPACE.to_owned());
-    let group = var("NACOS_GROUP").unwrap_or(constants::DEFAULT_GROUP.to_owned());
-    let cluster = var("NACOS_CLUSTER").unwrap_or(constants::DEFAULT_CLUSTER.to_owned());
-    let server_schema = var("NACOS_SERVER_SCHEMA").unwrap_or(constants::DEFAULT_SERVER_SCHEMA.to_owned());
-    let server_context = var("NACOS_SERVER_CONTEXT").unwrap_or(constants::DEFAULT_SERVER_CONTEXT.to_owned());
-     let servers = var("NACOS_SERVER_ADDRS").expect("env NACOS_SERVER_ADDRS is empty")
-        .split(",")
-        .map(|server| ServerConfig::new(server_schema.clone(), server.to_string(), server_context.clone()))
-        .collect::<Vec<_>>();
-    let failover_dir =var("NACOS_NAMING_FAILOVER").unwrap_or_else(|_|{
-        let home = var("HOME").expect("can't obtain home dir");
-        format!("{}/{}", home, constants::DEFAULT_FAILOVER_DIR)
-    });
-
-    let config = NamingConfig {
-        namespace_id: namespace,
-        cluster,
-        group,
-        server_list: servers,
-        cache_dir: failover_dir,
-        load_at_start: parse_bool_env("NACOS_NAMING_LOAD_AT_START", false),
-        update_when_empty: parse_bool_env("NACOS_NAMING_LOAD_AT_START", false),
-        user_name: var("NACOS_USERNAME").ok(),
-        password: var("NACOS_PASSWORD").ok(),
-    };
-    NamingClient::new_http(config).await
+/// tonic channels and registrations backed by a shared naming client
+pub struct TonicAdapter<R: NamingRemote> {
+    client: Arc<NamingClient<R>>,
+    default_options: ChannelOptions,
+    service_options: HashMap<String, ChannelOptions>
 }
 
-fn parse_bool_env(key: &str, default: bool) -> bool {
-    std::env::var(key).map(|res|{
-        match res.parse::<bool>() {
-            Ok(x) => x,
-            Err(error) => {
-                log::warn!("failed to parse nacos env[{}]: {}", key, error);
-                default
-            }
+impl<R: NamingRemote> Clone for TonicAdapter<R> {
+    fn clone(&self) -> Self {
+        TonicAdapter {
+            client: self.client.clone(),
+            default_options: self.default_options.clone(),
+            service_options: self.service_options.clone()
         }
-    }).unwrap_or(default)
-}
-struct ChangeListener {
-    cur_endpoints: Arc<Mutex<HashSet<String>>>,
-    queue: Arc<SegQueue<Change<String, Endpoint>>>,
+    }
 }
 
-impl ChangeListener {
-    fn gen_endpoints<'a>(&self, list: impl IntoIterator<Item = &'a String>) -> Vec<(String, Endpoint)> {
-        let mut ret = Vec::new();
-        for endpoint in list {
-            let ep = Endpoint::from_shared(endpoint.clone());
-            let ep = match ep {
-                Ok(endpoint) => endpoint,
-                Err(error) => {
-                    log::error!("invalid url: {}", error);
-                    continue
-                } 
-            };
-            ret.push((endpoint.clone(), ep));
+impl<R: NamingRemote + Clone + Send + 'static> TonicAdapter<R> {
+    pub fn new(client: Arc<NamingClient<R>>) -> Self {
+        TonicAdapter {
+            client,
+            default_options: ChannelOptions::default(),
+            service_options: HashMap::new()
         }
-        ret
     }
-}
 
-#[async_trait]
-impl ServiceChangeListener for ChangeListener {
-    async fn changed(&self, service_name: &str, hosts: Vec<Instance>) {
-        log::debug!("obtain service[{}] change from nacos: {:?}", service_name, hosts);
-        let endpoints = hosts.into_iter()
-            .filter(|instance| instance.healthy && instance.enabled && instance.weight > 0f64)
-            .map(|mut instance| {
-                let port = instance.metadata.remove("gRPC_port").unwrap_or(instance.port.to_string());
-                format!("{}://{}:{}", "http", instance.ip, port)
-            })
-            .collect::<HashSet<_>>();
-        
-        let mut cur = self.cur_endpoints.lock().await;
+    /// options of channels to services without their own options
+    pub fn with_default_options(mut self, options: ChannelOptions) -> Self {
+        self.default_options = options;
+        self
+    }
+
+    /// options of channels to the given service
+    pub fn with_service_options(mut self, service_name: &str, options: ChannelOptions) -> Self {
+        self.service_options.insert(service_name.to_string(), options);
+        self
+    }
 
-        // remove
-        let diffs = cur.difference(&endpoints).map(|x| x.clone()).collect::<Vec<_>>();
-        for remove_diff in diffs {
-            cur.remove(&remove_diff);
-            let change = Change::Remove(remove_diff.clone());
-            self.queue.push(change);
-            if self.queue.len() > 256 {
-                self.queue.pop();
-            }
-        }
-        
-        // insert
-        for (key, endpoint) in self.gen_endpoints(endpoints.difference(&cur)) {
-            cur.insert(key.clone());
-            let change = Change::Insert(key, endpoint);
-            self.queue.push(change);
-            if self.queue.len() > 256 {
-                self.queue.pop();
-            }
-        }
+    pub fn client(&self) -> &Arc<NamingClient<R>> {
+        &self.client
     }
-}
 
-pub async fn gen_channel(service_name: &str) -> Channel {
-    let nacos_client = NACOS_CLIENT.get().await;
-    let (channel, rx) = Channel::balance_channel(1);
-    let queue = Arc::new(SegQueue::new());
-    let listener = ChangeListener {cur_endpoints: Arc::new(Mutex::new(HashSet::new())), queue: queue.clone()};
-    let name_cloned = service_name.to_string();
-    tokio::spawn(async move {
-        loop {
-            if let Some(change) = queue.pop() {
-                log::info!("service[{}] change: {:?}", name_cloned, change);
-                if let Err(error) = rx.try_send(change) {
-                    log::debug!("failed to modify endpoint list: {}", error)
-                }
-            } else {
-                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-            }
-        }
-    });
-    let x = nacos_client.subscribe(
-        service_name, nacos_client.get_group(), vec![nacos_client.get_cluster()], listener
-    ).await;
-    if let Err(error) = x {
-        log::error!("failed to subscribe service change: {}", error);
-        return channel;
+    /// a load balanced channel over the instances of the service in the client's group and cluster
+    pub async fn gen_channel(&self, service_name: &str) -> Result<Channel> {
+        let (channel, balance) = Channel::balance_channel(1);
+        let (listener, snapshots) = discover::SnapshotListener::new();
+        let snapshot_tx = listener.sender();
+        let group = self.client.get_group();
+        let clusters = vec![self.client.get_cluster()];
+        self.client.subscribe(service_name, group, clusters.as_slice(), listener).await?;
+        // the subscription only reports changes, start from the instances already known
+        let hosts = self.client.select_instances(service_name, group, clusters.as_slice(), true).await?;
+        discover::seed(&snapshot_tx, hosts);
+        let options = self.service_options.get(service_name).unwrap_or(&self.default_options).clone();
+        tokio::spawn(discover::sync_endpoints(service_name.to_string(), snapshots, balance, options));
+        Ok(channel)
     }
-    channel
-}
 
-pub async fn register(service_name: &str, group: &str, cluster: &str, ip: &str, port: u16) -> Result<()> {
-    let nacos_client = NACOS_CLIENT.get().await;
-    let mut instance = Instance::new_with_required(service_name, group, cluster, ip, port);
-    instance.metadata.insert("gRPC_port".to_owned(), port.to_string());
-    nacos_client.register_instance(instance).await
-}
\ No newline at end of file
+    /// register a grpc server instance; it is deregistered when the returned guard is dropped
+    pub async fn register(
+        &self, service_name: &str, group: &str, cluster: &str, ip: &str, port: u16
+    ) -> Result<Registration<R>> {
+        let mut instance = Instance::new_with_required(service_name, group, cluster, ip, port);
+        instance.metadata.insert(METADATA_GRPC_PORT.to_owned(), port.to_string());
+        self.client.register_instance(instance.clone()).await?;
+        Ok(Registration::new(self.client.clone(), instance))
+    }
+}
@@ -0,0 +1,88 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use nacos_naming_client::{NamingClient, NamingRemote, model::Instance, error::{Error, Result}};
+
+use crate::{HealthAction, HealthBridge};
+
+
+/// time between disabling an instance and deregistering it,
+/// long enough for consumers to receive the change by push or polling
+pub const DEFAULT_DRAIN_PERIOD: Duration = Duration::from_secs(10);
+
+/// guard of an instance registered through the adapter;
+/// the instance is deregistered when the guard is dropped
+pub struct Registration<R: NamingRemote + Clone + Send + 'static> {
+    client: Arc<NamingClient<R>>,
+    instance: Instance,
+    registered: bool
+}
+
+impl<R: NamingRemote + Clone + Send + 'static> Registration<R> {
+    pub(crate) fn new(client: Arc<NamingClient<R>>, instance: Instance) -> Self {
+        Registration { client, instance, registered: true }
+    }
+
+    pub fn instance(&self) -> &Instance {
+        &self.instance
+    }
+
+    /// bridge reflecting the serving status of the server into this instance
+    pub fn health_bridge(&self, action: HealthAction) -> HealthBridge<R> {
+        HealthBridge::new(self.client.clone(), self.instance.clone(), action)
+    }
+
+    /// deregister the instance from nacos immediately
+    pub async fn deregister(mut self) -> Result<()> {
+        self.registered = false;
+        self.client.deregister_instance(self.instance.clone()).await
+    }
+
+    /// mark the instance disabled so consumers stop picking it,
+    /// wait out the drain period, then deregister it
+    pub async fn shutdown(mut self, drain: Duration) -> Result<()> {
+        let mut disabled = self.instance.clone();
+        disabled.enabled = false;
+        if let Err(error) = self.client.update_instance(disabled).await {
+            log::warn!("failed to disable instance[{}] before deregister: {}", self.instance.key(), error);
+        } else {
+            tokio::time::sleep(drain).await;
+        }
+        // dropping the guard while draining still deregisters the instance
+        self.registered = false;
+        self.client.deregister_instance(self.instance.clone()).await
+    }
+
+    /// future for tonic's `serve_with_shutdown`: waits for the signal, then runs [Registration::shutdown]
+    /// so the server keeps serving during the drain period
+    pub async fn shutdown_signal<F: Future<Output = ()>>(self, signal: F, drain: Duration) {
+        signal.await;
+        log::info!("shutdown signal received, drain instance[{}]", self.instance.key());
+        if let Err(error) = self.shutdown(drain).await {
+            log::error!("failed to deregister instance: {}", error);
+        }
+    }
+}
+
+impl<R: NamingRemote + Clone + Send + 'static> Drop for Registration<R> {
+    fn drop(&mut self) {
+        if !self.registered {
+            return;
+        }
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => {
+                log::warn!("no tokio runtime, instance[{}] stays registered until its beat expires", self.instance.key());
+                return;
+            }
+        };
+        let client = self.client.clone();
+        let instance = self.instance.clone();
+        handle.spawn(async move {
+            match client.deregister_instance(instance).await {
+                // the client deregistered its instances on shutdown
+                Ok(_) | Err(Error::Closed) => {},
+                Err(error) => log::error!("failed to deregister instance on drop: {}", error)
+            }
+        });
+    }
+}